@@ -1,8 +1,9 @@
 use std::{borrow::Cow, fmt, iter, marker::PhantomData, ops::Range};
 
 use crate::{
-    cursor, green::GreenTokenData, Direction, GreenNode, GreenNodeData, GreenToken, NodeOrToken,
-    SyntaxKind, SyntaxText, TextRange, TextSize, TokenAtOffset, WalkEvent,
+    ast::AstNode, cursor, green::GreenTokenData, Direction, GreenNode, GreenNodeData, GreenToken,
+    LineCol, LineIndex, NodeOrToken, SyntaxKind, SyntaxText, TextLen, TextRange, TextSize,
+    TokenAtOffset, WalkEvent,
 };
 
 pub trait Language: Sized + Copy + fmt::Debug + Eq + Ord + std::hash::Hash {
@@ -10,6 +11,48 @@ pub trait Language: Sized + Copy + fmt::Debug + Eq + Ord + std::hash::Hash {
 
     fn kind_from_raw(raw: SyntaxKind) -> Self::Kind;
     fn kind_to_raw(kind: Self::Kind) -> SyntaxKind;
+
+    /// Returns all kinds of the language. Implementations can list the kinds
+    /// manually or generate the list together with the `Kind` enum. Required
+    /// by [`KindTable`], by default no kinds are listed.
+    fn all_kinds() -> &'static [Self::Kind]
+    where
+        Self::Kind: 'static,
+    {
+        &[]
+    }
+}
+
+/// A lookup table with a value for every kind listed in
+/// `Language::all_kinds`, indexed by the raw value of the kind.
+#[derive(Debug, Clone)]
+pub struct KindTable<L: Language, T> {
+    values: Vec<Option<T>>,
+    _p: PhantomData<L>,
+}
+
+impl<L: Language, T> KindTable<L, T> {
+    /// Creates a table, computing the value for each kind with `f`.
+    pub fn new(mut f: impl FnMut(L::Kind) -> T) -> KindTable<L, T>
+    where
+        L::Kind: 'static,
+    {
+        let mut values = Vec::new();
+        for &kind in L::all_kinds() {
+            let idx = L::kind_to_raw(kind).0 as usize;
+            if values.len() <= idx {
+                values.resize_with(idx + 1, || None);
+            }
+            values[idx] = Some(f(kind));
+        }
+        KindTable { values, _p: PhantomData }
+    }
+
+    /// Returns the value for `kind`, or `None` if the kind isn't listed in
+    /// `Language::all_kinds`.
+    pub fn get(&self, kind: L::Kind) -> Option<&T> {
+        self.values.get(L::kind_to_raw(kind).0 as usize)?.as_ref()
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -98,6 +141,16 @@ impl<L: Language> SyntaxNode<L> {
     pub fn new_root(green: GreenNode) -> SyntaxNode<L> {
         SyntaxNode::from(cursor::SyntaxNode::new_root(green))
     }
+
+    /// Returns the green tree of the root of this tree, for example to cache
+    /// the tree without keeping the cursor alive. The tree can be recreated
+    /// with `SyntaxNode::new_root(green)`.
+    ///
+    /// If this is the last reference to the tree, the green node is moved out
+    /// of it without touching the refcount.
+    pub fn into_green(self) -> GreenNode {
+        self.raw.into_green()
+    }
     /// Returns a green tree, equal to the green tree this node
     /// belongs two, except with this node substitute. The complexity
     /// of operation is proportional to the depth of the tree
@@ -105,6 +158,19 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.replace_with(replacement)
     }
 
+    /// Returns the root of a new tree, equal to the tree this node belongs
+    /// to, except that the children of this node are replaced with
+    /// `new_children`. Only the nodes on the path from this node to the root
+    /// are rebuilt, the rest of the tree is shared.
+    pub fn replace_children_with<I>(&self, new_children: I) -> SyntaxNode<L>
+    where
+        I: IntoIterator<Item = NodeOrToken<GreenNode, GreenToken>>,
+    {
+        let new_children: Vec<_> = new_children.into_iter().collect();
+        let replacement = GreenNode::new(self.raw.kind(), new_children);
+        SyntaxNode::new_root(self.replace_with(replacement))
+    }
+
     pub fn kind(&self) -> L::Kind {
         L::kind_from_raw(self.raw.kind())
     }
@@ -117,14 +183,114 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.index()
     }
 
+    /// Returns `true` if this node is the first child of its parent, counting
+    /// tokens. Returns `false` for the root.
+    pub fn is_first_child(&self) -> bool {
+        self.raw.is_first_child()
+    }
+
+    /// Returns `true` if this node is the last child of its parent, counting
+    /// tokens. Returns `false` for the root.
+    pub fn is_last_child(&self) -> bool {
+        self.raw.is_last_child()
+    }
+
+    /// Returns the offset of this element relative to the start of its
+    /// parent, or zero for the root.
+    pub fn offset_in_parent(&self) -> TextSize {
+        self.raw.offset_in_parent()
+    }
+
+    /// Returns the range of this element relative to the start of its parent.
+    pub fn range_in_parent(&self) -> TextRange {
+        self.raw.range_in_parent()
+    }
+
     pub fn text(&self) -> SyntaxText {
         self.raw.text()
     }
 
+    /// Returns the number of child nodes, like `children().count()`, but
+    /// without creating cursors for the children.
+    pub fn child_count(&self) -> usize {
+        self.raw.child_count()
+    }
+
+    /// Returns the number of children including tokens, like
+    /// `children_with_tokens().count()`, in `O(1)`.
+    pub fn token_inclusive_child_count(&self) -> usize {
+        self.raw.token_inclusive_child_count()
+    }
+
+    /// Returns `true` if this node has no children, see
+    /// [`GreenNodeData::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Returns `true` if the text of this node is not empty.
+    pub fn has_text(&self) -> bool {
+        self.raw.has_text()
+    }
+
+    /// Like `text().to_string()`, but allocates the string with the exact
+    /// length of the text upfront instead of growing it.
+    pub fn text_to_string(&self) -> String {
+        let text = self.text();
+        let mut buf = String::with_capacity(usize::from(text.len()));
+        text.for_each_chunk(|chunk| buf.push_str(chunk));
+        buf
+    }
+
+    /// Returns the start and end positions of this node. `line_index` must
+    /// be built from the text of the root node. Like in [`LineCol`], lines
+    /// and columns are zero-based and columns are counted in UTF-16 code
+    /// units.
+    pub fn line_col_range(&self, line_index: &LineIndex) -> (LineCol, LineCol) {
+        let range = self.text_range();
+        (line_index.line_col(range.start()), line_index.line_col(range.end()))
+    }
+
+    /// Returns the text of this node as if `target` had `new_text` as its
+    /// text, without rebuilding the tree. If `target` is not in the subtree
+    /// of this node, this is the same as `text().to_string()`.
+    pub fn text_with_replacement(&self, target: &SyntaxToken<L>, new_text: &str) -> String {
+        let mut buf = String::with_capacity(usize::from(self.text_range().len()));
+        for token in self.descendants_with_tokens().filter_map(|it| it.into_token()) {
+            if token == *target {
+                buf.push_str(new_text);
+            } else {
+                buf.push_str(token.text());
+            }
+        }
+        buf
+    }
+
     pub fn green(&self) -> Cow<'_, GreenNodeData> {
         self.raw.green()
     }
 
+    /// Returns a hash of the kinds and text of this subtree, which doesn't
+    /// depend on the position of the node. Use `content_eq` to resolve
+    /// collisions.
+    pub fn content_hash(&self) -> u64 {
+        self.raw.content_hash()
+    }
+
+    /// Returns `true` if the subtrees of both nodes have the same kinds and
+    /// text, regardless of their positions.
+    pub fn content_eq(&self, other: &SyntaxNode<L>) -> bool {
+        self.raw.content_eq(&other.raw)
+    }
+
+    /// Returns `true` if both nodes share the same green node, which is an
+    /// `O(1)` check. After an incremental reparse, this holds for the nodes
+    /// which were reused from the old tree. Unlike `content_eq`, it can
+    /// return `false` for equal subtrees which were built separately.
+    pub fn green_ptr_eq(&self, other: &SyntaxNode<L>) -> bool {
+        self.raw.green_ptr_eq(&other.raw)
+    }
+
     pub fn parent(&self) -> Option<SyntaxNode<L>> {
         self.raw.parent().map(Self::from)
     }
@@ -133,17 +299,85 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.ancestors().map(SyntaxNode::from)
     }
 
+    /// Returns the nearest proper ancestor of this node which can be cast to
+    /// `N`, for example the function containing an expression.
+    pub fn ancestor_of<N: AstNode<Language = L>>(&self) -> Option<N> {
+        self.parent()?.ancestors().find_map(N::cast)
+    }
+
+    /// Like [`ancestor_of`](Self::ancestor_of), but returns this node itself
+    /// if it can be cast to `N`.
+    pub fn self_or_ancestor_of<N: AstNode<Language = L>>(&self) -> Option<N> {
+        self.ancestors().find_map(N::cast)
+    }
+
+    /// Returns the root of the tree this node belongs to.
+    pub fn root(&self) -> SyntaxNode<L> {
+        SyntaxNode::from(self.raw.root())
+    }
+
+    /// Returns `true` if this node has no parent.
+    pub fn is_root(&self) -> bool {
+        self.raw.is_root()
+    }
+
+    /// Returns the identity of this node, which is cheaper to produce and to
+    /// use as a map key than a `SyntaxNodePtr`. See [`cursor::NodeId`].
+    pub fn node_id(&self) -> cursor::NodeId {
+        self.raw.node_id()
+    }
+
+    /// Returns `true` if this node or one of its ancestors has the given
+    /// kind. Unlike `ancestors`, this doesn't create a cursor for every
+    /// ancestor.
+    pub fn has_ancestor_of_kind(&self, kind: L::Kind) -> bool {
+        self.raw.has_ancestor_of_kind(L::kind_to_raw(kind))
+    }
+
+    /// Returns `true` if `ancestor` is one of [`ancestors`](Self::ancestors)
+    /// of this node, including the node itself. Nodes outside the range of
+    /// `ancestor` are rejected without walking up the tree, and nodes of a
+    /// different tree are rejected by comparing the roots before checking
+    /// the ancestors.
+    pub fn is_descendant_of(&self, ancestor: &SyntaxNode<L>) -> bool {
+        self.raw.is_descendant_of(&ancestor.raw)
+    }
+
     pub fn children(&self) -> SyntaxNodeChildren<L> {
         SyntaxNodeChildren { raw: self.raw.children(), _p: PhantomData }
     }
 
+    /// Returns the child nodes of the given kind. Children of other kinds are
+    /// skipped without creating cursors for them.
+    pub fn children_by_kind(&self, kind: L::Kind) -> impl Iterator<Item = SyntaxNode<L>> {
+        self.raw.children_by_kind(L::kind_to_raw(kind)).map(SyntaxNode::from)
+    }
+
     pub fn children_with_tokens(&self) -> SyntaxElementChildren<L> {
         SyntaxElementChildren { raw: self.raw.children_with_tokens(), _p: PhantomData }
     }
 
+    /// Returns the child nodes, each paired with `true` if it is the last
+    /// one, for example to skip the trailing separator.
+    pub fn children_marking_last(&self) -> impl Iterator<Item = (SyntaxNode<L>, bool)> {
+        mark_last(self.children())
+    }
+
+    /// Like [`children_marking_last`](Self::children_marking_last), but
+    /// includes tokens.
+    pub fn children_with_tokens_marking_last(
+        &self,
+    ) -> impl Iterator<Item = (SyntaxElement<L>, bool)> {
+        mark_last(self.children_with_tokens())
+    }
+
     pub fn first_child(&self) -> Option<SyntaxNode<L>> {
         self.raw.first_child().map(Self::from)
     }
+    /// Returns the first child node of the given kind.
+    pub fn first_child_by_kind(&self, kind: L::Kind) -> Option<SyntaxNode<L>> {
+        self.raw.first_child_by_kind(L::kind_to_raw(kind)).map(Self::from)
+    }
     pub fn last_child(&self) -> Option<SyntaxNode<L>> {
         self.raw.last_child().map(Self::from)
     }
@@ -158,10 +392,31 @@ impl<L: Language> SyntaxNode<L> {
     pub fn next_sibling(&self) -> Option<SyntaxNode<L>> {
         self.raw.next_sibling().map(Self::from)
     }
+    /// Returns the first following sibling node of the given kind.
+    pub fn next_sibling_by_kind(&self, kind: L::Kind) -> Option<SyntaxNode<L>> {
+        self.raw.next_sibling_by_kind(L::kind_to_raw(kind)).map(Self::from)
+    }
     pub fn prev_sibling(&self) -> Option<SyntaxNode<L>> {
         self.raw.prev_sibling().map(Self::from)
     }
 
+    /// Returns the node following this one in preorder: the first child if
+    /// there is one, otherwise the next sibling of the closest ancestor
+    /// (including this node) which has one.
+    pub fn next_node(&self) -> Option<SyntaxNode<L>> {
+        self.first_child().or_else(|| self.ancestors().find_map(|it| it.next_sibling()))
+    }
+
+    /// Returns the node preceding this one in preorder: the deepest last
+    /// descendant of the previous sibling, or the parent if there is no
+    /// previous sibling.
+    pub fn prev_node(&self) -> Option<SyntaxNode<L>> {
+        match self.prev_sibling() {
+            Some(sibling) => iter::successors(Some(sibling), |it| it.last_child()).last(),
+            None => self.parent(),
+        }
+    }
+
     pub fn next_sibling_or_token(&self) -> Option<SyntaxElement<L>> {
         self.raw.next_sibling_or_token().map(NodeOrToken::from)
     }
@@ -182,6 +437,8 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.siblings(direction).map(SyntaxNode::from)
     }
 
+    /// Returns the siblings of this node in the given direction, including
+    /// tokens. The first element is always this node itself.
     pub fn siblings_with_tokens(
         &self,
         direction: Direction,
@@ -189,32 +446,317 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.siblings_with_tokens(direction).map(SyntaxElement::from)
     }
 
+    /// Returns this node followed by the siblings after it, in document order.
+    pub fn self_and_following_siblings(&self) -> impl Iterator<Item = SyntaxElement<L>> {
+        self.siblings_with_tokens(Direction::Next)
+    }
+
+    /// Returns this node followed by the siblings before it, from the closest
+    /// one to the first child of the parent.
+    pub fn self_and_preceding_siblings(&self) -> impl Iterator<Item = SyntaxElement<L>> {
+        self.siblings_with_tokens(Direction::Prev)
+    }
+
     pub fn descendants(&self) -> impl Iterator<Item = SyntaxNode<L>> {
         self.raw.descendants().map(SyntaxNode::from)
     }
 
+    /// Like `descendants`, but in reverse order: the last node in preorder
+    /// comes first and the current node comes last.
+    pub fn descendants_rev(&self) -> impl Iterator<Item = SyntaxNode<L>> {
+        let root = self.clone();
+        let first = iter::successors(Some(self.clone()), |it| it.last_child()).last();
+        iter::successors(first, move |node| if *node == root { None } else { node.prev_node() })
+    }
+
+    /// Like `descendants`, but doesn't descend into nodes deeper than
+    /// `max_depth`. The current node has depth `0`, so `max_depth == 0`
+    /// yields only the node itself.
+    pub fn descendants_to_depth(&self, max_depth: usize) -> impl Iterator<Item = SyntaxNode<L>> {
+        let mut preorder = self.preorder();
+        let mut depth = 0;
+        iter::from_fn(move || loop {
+            match preorder.next()? {
+                WalkEvent::Enter(node) => {
+                    if depth == max_depth {
+                        preorder.skip_subtree();
+                    }
+                    depth += 1;
+                    return Some(node);
+                }
+                WalkEvent::Leave(_) => depth -= 1,
+            }
+        })
+    }
+
+    /// Like `descendants`, but pairs each node with its text.
+    pub fn descendants_with_text(&self) -> impl Iterator<Item = (SyntaxNode<L>, SyntaxText)> {
+        self.descendants().map(|node| {
+            let text = node.text();
+            (node, text)
+        })
+    }
+
     pub fn descendants_with_tokens(&self) -> impl Iterator<Item = SyntaxElement<L>> {
         self.raw.descendants_with_tokens().map(NodeOrToken::from)
     }
 
+    /// Returns all tokens of the given kind in the subtree of this node.
+    pub fn tokens_of_kind(&self, kind: L::Kind) -> impl Iterator<Item = SyntaxToken<L>> {
+        self.descendants_with_tokens()
+            .filter_map(|it| it.into_token())
+            .filter(move |it| it.kind() == kind)
+    }
+
+    /// Returns all tokens in the subtree of this node whose kind is one of
+    /// `kinds`.
+    pub fn tokens_of_kinds<'a>(
+        &self,
+        kinds: &'a [L::Kind],
+    ) -> impl Iterator<Item = SyntaxToken<L>> + 'a
+    where
+        L: 'a,
+    {
+        self.descendants_with_tokens()
+            .filter_map(|it| it.into_token())
+            .filter(move |it| kinds.contains(&it.kind()))
+    }
+
+    /// Returns the indentation of the line on which this node starts, that is,
+    /// the leading characters of the line for which `is_ws` returns `true`.
+    pub fn indent(&self, is_ws: impl Fn(char) -> bool) -> SyntaxText {
+        let root = self.root();
+        let start = self.text_range().start();
+
+        let mut line_start = TextSize::from(0);
+        let mut token = root.token_at_offset(start).left_biased();
+        while let Some(it) = token {
+            let token_start = it.text_range().start();
+            let len = std::cmp::min(
+                it.text_range().len(),
+                start.checked_sub(token_start).unwrap_or_default(),
+            );
+            if let Some(pos) = it.text()[..usize::from(len)].rfind('\n') {
+                line_start = token_start + TextSize::from(pos as u32 + 1);
+                break;
+            }
+            token = it.prev_token();
+        }
+
+        let line = root.text().slice(line_start..start);
+        let mut indent_len = TextSize::from(0);
+        let _ = line.try_for_each_chunk(|chunk| {
+            for c in chunk.chars() {
+                if !is_ws(c) {
+                    return Err(());
+                }
+                indent_len += c.text_len();
+            }
+            Ok(())
+        });
+        line.slice(..indent_len)
+    }
+
+    /// Returns the range of this node without leading and trailing trivia
+    /// tokens, that is, from the start of the first non-trivia token to the
+    /// end of the last one. If the node consists only of trivia, returns the
+    /// whole range.
+    pub fn significant_range(&self, is_trivia: impl Fn(L::Kind) -> bool) -> TextRange {
+        let mut significant = self
+            .descendants_with_tokens()
+            .filter_map(|it| it.into_token())
+            .filter(|it| !is_trivia(it.kind()));
+        let first = match significant.next() {
+            Some(it) => it,
+            None => return self.text_range(),
+        };
+        // Not `last_token`, which is `None` if the node ends with an empty node.
+        let last = significant.last().unwrap_or_else(|| first.clone());
+        TextRange::new(first.text_range().start(), last.text_range().end())
+    }
+
+    /// Returns the ranges and kinds of all tokens of one of `kinds` which
+    /// overlap `viewport`, in order, for example for syntax highlighting of
+    /// the visible part of a file. Subtrees outside of `viewport` are
+    /// skipped, and no cursors are created for the visited ones.
+    pub fn highlight_ranges(
+        &self,
+        viewport: TextRange,
+        kinds: &[L::Kind],
+    ) -> Vec<(TextRange, L::Kind)> {
+        let overlaps =
+            |range: TextRange| range.start() < viewport.end() && viewport.start() < range.end();
+        let green = self.green();
+        let mut res = Vec::new();
+        let mut stack = vec![(self.text_range().start(), green.children())];
+        while let Some((offset, children)) = stack.last_mut() {
+            let child = match children.next() {
+                Some(it) => it,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let range = TextRange::at(*offset, child.text_len());
+            *offset = range.end();
+            if !overlaps(range) {
+                continue;
+            }
+            match child {
+                NodeOrToken::Node(node) => stack.push((range.start(), node.children())),
+                NodeOrToken::Token(token) => {
+                    let kind = L::kind_from_raw(token.kind());
+                    if kinds.contains(&kind) {
+                        res.push((range, kind));
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the range of every maximal run of trivia tokens in this node,
+    /// in order. Leading and trailing trivia of the node are gaps as well,
+    /// even though they have a significant token on one side only; they
+    /// can be recognized by starting at the start or ending at the end of
+    /// the node. An empty trivia token between two significant tokens
+    /// yields an empty range.
+    pub fn trivia_gaps(
+        &self,
+        is_trivia: impl Fn(L::Kind) -> bool,
+    ) -> impl Iterator<Item = TextRange> {
+        let mut tokens = self.descendants_with_tokens().filter_map(|it| it.into_token());
+        iter::from_fn(move || {
+            let first = tokens.by_ref().find(|it| is_trivia(it.kind()))?;
+            let mut range = first.text_range();
+            for token in tokens.by_ref() {
+                if !is_trivia(token.kind()) {
+                    break;
+                }
+                range = range.cover(token.text_range());
+            }
+            Some(range)
+        })
+    }
+
     /// Traverse the subtree rooted at the current node (including the current
     /// node) in preorder, excluding tokens.
     pub fn preorder(&self) -> Preorder<L> {
         Preorder { raw: self.raw.preorder(), _p: PhantomData }
     }
 
+    /// Calls `f` for every node of the subtree in preorder, like iterating
+    /// over [`preorder`](Self::preorder), but reuses the cursor of a node for
+    /// its next sibling unless `f` clones it. New cursors are only created
+    /// for the first child nodes of nodes, which avoids most allocations in
+    /// walks over wide trees which only read the nodes.
+    pub fn visit(&self, f: impl FnMut(WalkEvent<&SyntaxNode<L>>)) {
+        cursor::visit(self, f)
+    }
+
     /// Traverse the subtree rooted at the current node (including the current
     /// node) in preorder, including tokens.
     pub fn preorder_with_tokens(&self) -> PreorderWithTokens<L> {
         PreorderWithTokens { raw: self.raw.preorder_with_tokens(), _p: PhantomData }
     }
 
+    /// Returns `true` if this node or any node or token in its subtree has
+    /// the given kind. Stops at the first match.
+    pub fn contains_kind(&self, kind: L::Kind) -> bool {
+        self.raw.contains_kind(L::kind_to_raw(kind))
+    }
+
+    /// Like `preorder_with_tokens`, but also yields the depth of each element
+    /// relative to the current node, which has depth `0`. Both `Enter` and
+    /// `Leave` events of an element have the same depth.
+    pub fn preorder_with_depth(
+        &self,
+    ) -> impl Iterator<Item = (usize, WalkEvent<SyntaxElement<L>>)> {
+        self.preorder_with_tokens().scan(0, |depth, event| {
+            let res = match &event {
+                WalkEvent::Enter(_) => {
+                    *depth += 1;
+                    *depth - 1
+                }
+                WalkEvent::Leave(_) => {
+                    *depth -= 1;
+                    *depth
+                }
+            };
+            Some((res, event))
+        })
+    }
+
     /// Find a token in the subtree corresponding to this node, which covers the offset.
     /// Precondition: offset must be withing node's range.
     pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken<L>> {
         self.raw.token_at_offset(offset).map(SyntaxToken::from)
     }
 
+    /// Returns the boundary of a node or token in this subtree which is
+    /// closest to `offset`, which is `offset` itself if it is already at a
+    /// boundary. As every node starts and ends at a token boundary, this is
+    /// the closer end of the token containing `offset`, or its start if both
+    /// are equally far. Offsets outside of this node snap to its start or
+    /// end.
+    pub fn nearest_boundary(&self, offset: TextSize) -> TextSize {
+        let range = self.text_range();
+        let offset = offset.clamp(range.start(), range.end());
+        match self.token_at_offset(offset) {
+            TokenAtOffset::Single(token) => {
+                let range = token.text_range();
+                if range.end() - offset < offset - range.start() {
+                    range.end()
+                } else {
+                    range.start()
+                }
+            }
+            TokenAtOffset::None | TokenAtOffset::Between(..) => offset,
+        }
+    }
+
+    /// Returns the non-trivia token at `offset`, preferring the left one if
+    /// `offset` is between two tokens. If only trivia is at `offset`, returns
+    /// the closest non-trivia token of this subtree, again preferring the
+    /// left one on ties.
+    pub fn meaningful_token_at(
+        &self,
+        offset: TextSize,
+        is_trivia: impl Fn(L::Kind) -> bool,
+    ) -> Option<SyntaxToken<L>> {
+        let token = match self.token_at_offset(offset) {
+            TokenAtOffset::None => return None,
+            TokenAtOffset::Single(it) => it,
+            TokenAtOffset::Between(left, right) => {
+                if is_trivia(left.kind()) && !is_trivia(right.kind()) {
+                    right
+                } else {
+                    left
+                }
+            }
+        };
+        if !is_trivia(token.kind()) {
+            return Some(token);
+        }
+
+        let range = self.text_range();
+        let find = |step: fn(&SyntaxToken<L>) -> Option<SyntaxToken<L>>| {
+            iter::successors(step(&token), step)
+                .take_while(|it| range.contains_range(it.text_range()))
+                .find(|it| !is_trivia(it.kind()))
+        };
+        match (find(SyntaxToken::prev_token), find(SyntaxToken::next_token)) {
+            (Some(left), Some(right)) => {
+                if right.text_range().start() - offset < offset - left.text_range().end() {
+                    Some(right)
+                } else {
+                    Some(left)
+                }
+            }
+            (left, right) => left.or(right),
+        }
+    }
+
     /// Return the deepest node or token in the current subtree that fully
     /// contains the range. If the range is empty and is contained in two leaf
     /// nodes, either one can be returned. Precondition: range must be contained
@@ -223,6 +765,61 @@ impl<L: Language> SyntaxNode<L> {
         NodeOrToken::from(self.raw.covering_element(range))
     }
 
+    /// Returns the element at each of `offsets`, which must be sorted and
+    /// within this node: the token containing the offset, or the one to the
+    /// right if the offset is between two tokens. The last offset of the
+    /// node resolves to the last token, and offsets in a node without
+    /// tokens to the node itself.
+    ///
+    /// All offsets are resolved in a single walk, which only visits the
+    /// subtrees containing an offset. This is faster than calling
+    /// `token_at_offset` for each of many offsets.
+    pub fn elements_at_offsets(&self, offsets: &[TextSize]) -> Vec<SyntaxElement<L>> {
+        let range = self.text_range();
+        assert!(
+            offsets.iter().all(|&it| range.start() <= it && it <= range.end()),
+            "offsets must be within {:?}",
+            range
+        );
+        debug_assert!(offsets.windows(2).all(|it| it[0] <= it[1]), "offsets must be sorted");
+
+        let mut res = Vec::with_capacity(offsets.len());
+        let mut stack = vec![self.children_with_tokens()];
+        while let Some(children) = stack.last_mut() {
+            let offset = match offsets.get(res.len()) {
+                Some(&it) => it,
+                None => break,
+            };
+            match children.next() {
+                Some(child) if child.text_range().end() <= offset => (),
+                Some(NodeOrToken::Node(it)) => stack.push(it.children_with_tokens()),
+                Some(NodeOrToken::Token(it)) => {
+                    let end = it.text_range().end();
+                    let count = offsets[res.len()..].iter().take_while(|&&o| o < end).count();
+                    res.resize(res.len() + count, NodeOrToken::Token(it));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        // Only offsets at the end of this node are left.
+        if res.len() < offsets.len() {
+            let rest = match self.last_token() {
+                Some(it) => it.into(),
+                None => self.clone().into(),
+            };
+            res.resize(offsets.len(), rest);
+        }
+        res
+    }
+
+    /// Returns the token which fully contains the range, or `None` if the
+    /// range is not contained in a single token of this subtree.
+    pub fn token_covering(&self, range: TextRange) -> Option<SyntaxToken<L>> {
+        self.raw.token_covering(range).map(SyntaxToken::from)
+    }
+
     /// Finds a [`SyntaxElement`] which intersects with a given `range`. If
     /// there are several intersecting elements, any one can be returned.
     ///
@@ -232,6 +829,35 @@ impl<L: Language> SyntaxNode<L> {
         self.raw.child_or_token_at_range(range).map(SyntaxElement::from)
     }
 
+    /// Returns the run of consecutive children of this node, including
+    /// tokens, whose combined range is exactly `range`. Returns an empty
+    /// vector if `range` is empty or doesn't start and end at the
+    /// boundaries of children.
+    pub fn elements_covering_exactly(&self, range: TextRange) -> Vec<SyntaxElement<L>> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let mut res = Vec::new();
+        for child in self.children_with_tokens() {
+            let child_range = child.text_range();
+            if child_range.end() <= range.start() && res.is_empty() {
+                continue;
+            }
+            if res.is_empty() && child_range.start() != range.start() {
+                break;
+            }
+            if child_range.end() > range.end() {
+                break;
+            }
+            let done = child_range.end() == range.end();
+            res.push(child);
+            if done {
+                return res;
+            }
+        }
+        Vec::new()
+    }
+
     /// Returns an independent copy of the subtree rooted at this node.
     ///
     /// The parent of the returned node will be `None`, the start offset will be
@@ -262,6 +888,17 @@ impl<L: Language> SyntaxToken<L> {
         self.raw.replace_with(new_token)
     }
 
+    /// Returns the root of a new tree, equal to the tree this token belongs
+    /// to, except that this token has the given text. Only the nodes on the
+    /// path from this token to the root are rebuilt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the token is detached, as there is no tree to rebuild then.
+    pub fn with_text(&self, text: &str) -> SyntaxNode<L> {
+        SyntaxNode::from(self.raw.with_text(text))
+    }
+
     pub fn kind(&self) -> L::Kind {
         L::kind_from_raw(self.raw.kind())
     }
@@ -270,14 +907,37 @@ impl<L: Language> SyntaxToken<L> {
         self.raw.text_range()
     }
 
+    /// Returns the index of this token among all children of its parent,
+    /// nodes included. This is the index of the token in the children of
+    /// the parent's green node, so it can be passed to
+    /// [`GreenNodeData::replace_child`] together with `parent().green()`.
     pub fn index(&self) -> usize {
         self.raw.index()
     }
 
+    /// Returns the offset of this element relative to the start of its
+    /// parent, or zero for the root.
+    pub fn offset_in_parent(&self) -> TextSize {
+        self.raw.offset_in_parent()
+    }
+
+    /// Returns the range of this element relative to the start of its parent.
+    pub fn range_in_parent(&self) -> TextRange {
+        self.raw.range_in_parent()
+    }
+
     pub fn text(&self) -> &str {
         self.raw.text()
     }
 
+    /// Returns the text of this token as a `SyntaxText`, like the text of a
+    /// node. `text` is cheaper if a `&str` is enough.
+    pub fn syntax_text(&self) -> SyntaxText {
+        self.raw.syntax_text()
+    }
+
+    /// Returns the green token of this token. Use `to_owned` to get a
+    /// [`GreenToken`].
     pub fn green(&self) -> &GreenTokenData {
         self.raw.green()
     }
@@ -290,6 +950,12 @@ impl<L: Language> SyntaxToken<L> {
         self.raw.ancestors().map(SyntaxNode::from)
     }
 
+    /// Returns the root of the tree this token belongs to, or `None` if the
+    /// token is detached.
+    pub fn root(&self) -> Option<SyntaxNode<L>> {
+        self.raw.root().map(SyntaxNode::from)
+    }
+
     pub fn next_sibling_or_token(&self) -> Option<SyntaxElement<L>> {
         self.raw.next_sibling_or_token().map(NodeOrToken::from)
     }
@@ -313,6 +979,117 @@ impl<L: Language> SyntaxToken<L> {
         self.raw.prev_token().map(SyntaxToken::from)
     }
 
+    /// Returns the range and the text between this token and the next token
+    /// for which `is_trivia` returns `false`. The gap can span several
+    /// trivia tokens in different nodes. If there's no such token, the gap
+    /// extends to the end of the last trivia token, which is the end of the
+    /// tree.
+    pub fn following_gap(&self, is_trivia: impl Fn(L::Kind) -> bool) -> (TextRange, SyntaxText) {
+        let start = self.text_range().end();
+        let mut end = start;
+        let mut token = self.next_token();
+        while let Some(it) = token {
+            if !is_trivia(it.kind()) {
+                end = it.text_range().start();
+                break;
+            }
+            end = it.text_range().end();
+            token = it.next_token();
+        }
+        let range = TextRange::new(start, end);
+        let text = match self.ancestors().find(|it| it.text_range().contains_range(range)) {
+            Some(node) => node.text().slice(range - node.text_range().start()),
+            // Only a detached token has no ancestors, and then the gap is empty.
+            None => self.syntax_text().slice(TextRange::empty(self.text_range().len())),
+        };
+        (range, text)
+    }
+
+    /// If this token is an `open` or `close` delimiter, returns the matching
+    /// delimiter, by scanning the following or preceding tokens and counting
+    /// nested pairs. Returns `None` if this token is not a delimiter or if
+    /// there is no match before the end of the tree.
+    pub fn matching_delimiter(&self, open: L::Kind, close: L::Kind) -> Option<SyntaxToken<L>> {
+        let (step, inner, outer): (fn(&Self) -> Option<Self>, _, _) = if self.kind() == open {
+            (SyntaxToken::next_token, open, close)
+        } else if self.kind() == close {
+            (SyntaxToken::prev_token, close, open)
+        } else {
+            return None;
+        };
+        let mut depth = 0usize;
+        let mut token = step(self);
+        while let Some(it) = token {
+            if it.kind() == inner {
+                depth += 1;
+            } else if it.kind() == outer {
+                if depth == 0 {
+                    return Some(it);
+                }
+                depth -= 1;
+            }
+            token = step(&it);
+        }
+        None
+    }
+
+    /// Returns the text of the line this token is on, from the last newline
+    /// before the token to the first newline after it, excluding the
+    /// newlines themselves. Newlines are the characters for which
+    /// `is_newline` returns `true`. If the token itself contains a newline,
+    /// like a whitespace token, this is the line the token starts on.
+    pub fn line_text(&self, is_newline: impl Fn(char) -> bool) -> SyntaxText {
+        let text = match self.root() {
+            Some(root) => root.text(),
+            None => self.syntax_text(),
+        };
+
+        let mut start = TextSize::from(0);
+        let mut token = self.prev_token();
+        while let Some(it) = token {
+            let newline = it.text().char_indices().rev().find(|&(_, c)| is_newline(c));
+            if let Some((pos, c)) = newline {
+                start = it.text_range().start() + TextSize::from((pos + c.len_utf8()) as u32);
+                break;
+            }
+            token = it.prev_token();
+        }
+
+        let mut end = text.len();
+        let mut token = Some(self.clone());
+        while let Some(it) = token {
+            if let Some(pos) = it.text().find(&is_newline) {
+                end = it.text_range().start() + TextSize::from(pos as u32);
+                break;
+            }
+            token = it.next_token();
+        }
+
+        text.slice(start..end)
+    }
+
+    /// Returns `true` if this token is the first non-trivia token on its
+    /// line, that is, if it is only preceded by trivia tokens up to a newline
+    /// or the start of the tree. Newlines are the characters for which
+    /// `is_newline` returns `true`.
+    pub fn is_at_line_start(
+        &self,
+        is_trivia: impl Fn(&SyntaxToken<L>) -> bool,
+        is_newline: impl Fn(char) -> bool,
+    ) -> bool {
+        let mut token = self.prev_token();
+        while let Some(it) = token {
+            if !is_trivia(&it) {
+                return false;
+            }
+            if it.text().contains(&is_newline) {
+                return true;
+            }
+            token = it.prev_token();
+        }
+        true
+    }
+
     pub fn detach(&self) {
         self.raw.detach()
     }
@@ -375,6 +1152,50 @@ impl<L: Language> SyntaxElement<L> {
     }
 }
 
+/// A focus on a node of a tree, which can be moved up, down, and across
+/// siblings.
+///
+/// Moving the focus reuses the already existing parent chain, so navigating
+/// back and forth around a node is cheap.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Zipper<L: Language> {
+    focus: SyntaxNode<L>,
+}
+
+impl<L: Language> Zipper<L> {
+    pub fn new(focus: SyntaxNode<L>) -> Zipper<L> {
+        Zipper { focus }
+    }
+
+    pub fn focus(&self) -> &SyntaxNode<L> {
+        &self.focus
+    }
+
+    pub fn into_focus(self) -> SyntaxNode<L> {
+        self.focus
+    }
+
+    /// Moves the focus to the parent node.
+    pub fn up(&self) -> Option<Zipper<L>> {
+        self.focus.parent().map(Zipper::new)
+    }
+
+    /// Moves the focus to the first child node.
+    pub fn down(&self) -> Option<Zipper<L>> {
+        self.focus.first_child().map(Zipper::new)
+    }
+
+    /// Moves the focus to the previous sibling node.
+    pub fn left(&self) -> Option<Zipper<L>> {
+        self.focus.prev_sibling().map(Zipper::new)
+    }
+
+    /// Moves the focus to the next sibling node.
+    pub fn right(&self) -> Option<Zipper<L>> {
+        self.focus.next_sibling().map(Zipper::new)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SyntaxNodeChildren<L: Language> {
     raw: cursor::SyntaxNodeChildren,
@@ -437,6 +1258,15 @@ impl<L: Language> Iterator for PreorderWithTokens<L> {
     }
 }
 
+impl<L: Language> cursor::Visit for SyntaxNode<L> {
+    fn first_child(&self) -> Option<SyntaxNode<L>> {
+        self.first_child()
+    }
+    fn into_next_sibling(self) -> Option<SyntaxNode<L>> {
+        self.raw.into_next_sibling().map(SyntaxNode::from)
+    }
+}
+
 impl<L: Language> From<cursor::SyntaxNode> for SyntaxNode<L> {
     fn from(raw: cursor::SyntaxNode) -> SyntaxNode<L> {
         SyntaxNode { raw, _p: PhantomData }
@@ -478,3 +1308,979 @@ impl<L: Language> From<SyntaxElement<L>> for cursor::SyntaxElement {
         }
     }
 }
+
+fn mark_last<I: Iterator>(iter: I) -> impl Iterator<Item = (I::Item, bool)> {
+    let mut iter = iter.peekable();
+    iter::from_fn(move || {
+        let item = iter.next()?;
+        Some((item, iter.peek().is_none()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum TestLanguage {}
+
+    impl Language for TestLanguage {
+        type Kind = SyntaxKind;
+
+        fn kind_from_raw(raw: SyntaxKind) -> SyntaxKind {
+            raw
+        }
+        fn kind_to_raw(kind: SyntaxKind) -> SyntaxKind {
+            kind
+        }
+    }
+
+    const WS: SyntaxKind = SyntaxKind(2);
+
+    /// Builds a tree from its s-expression, see `GreenNode::from_sexpr`.
+    fn tree(sexpr: &str) -> SyntaxNode<TestLanguage> {
+        let green = GreenNode::from_sexpr(sexpr, |kind| SyntaxKind(kind.parse().unwrap())).unwrap();
+        SyntaxNode::new_root(green)
+    }
+
+    fn token(root: &SyntaxNode<TestLanguage>, text: &str) -> SyntaxToken<TestLanguage> {
+        root.descendants_with_tokens()
+            .filter_map(|it| it.into_token())
+            .find(|it| it.text() == text)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_is_at_line_start() {
+        let is_trivia = |it: &SyntaxToken<TestLanguage>| it.kind() == WS;
+        let root = tree(r#"(0 1:"a" 2:" " (3 1:"b" 2:"\r") 2:"  " 1:"c" 2:"\n" 1:"d")"#);
+
+        let at_line_start = |text, is_newline: fn(char) -> bool| {
+            token(&root, text).is_at_line_start(is_trivia, is_newline)
+        };
+        assert!(at_line_start("a", |c| c == '\n'));
+        assert!(!at_line_start("b", |c| c == '\n'));
+        assert!(!at_line_start("c", |c| c == '\n'));
+        assert!(at_line_start("c", |c| c == '\n' || c == '\r'));
+        assert!(at_line_start("d", |c| c == '\n'));
+    }
+
+    #[test]
+    fn test_following_gap() {
+        let is_trivia = |kind| kind == WS;
+        let root = tree(r#"(0 (3 1:"a" 2:" " (3 2:"\n" 1:"b") (3 1:"c")) 1:"d" 2:" ")"#);
+        let gap = |text| {
+            let (range, text) = token(&root, text).following_gap(is_trivia);
+            (u32::from(range.start())..u32::from(range.end()), text.to_string())
+        };
+        assert_eq!(gap("a"), (1..3, " \n".to_string()));
+        assert_eq!(gap("b"), (4..4, String::new()));
+        assert_eq!(gap("c"), (5..5, String::new()));
+        assert_eq!(gap("d"), (6..7, " ".to_string()));
+
+        let nested = tree(r#"(0 (3 1:"a" (3 1:"d")) 2:" ")"#);
+        let (range, text) = token(&nested, "d").following_gap(is_trivia);
+        assert_eq!(range, TextRange::new(2.into(), 3.into()));
+        assert_eq!(text, " ");
+
+        let root = root.clone_for_update();
+        let d = token(&root, "d");
+        d.detach();
+        let (range, text) = d.following_gap(is_trivia);
+        assert_eq!(range, TextRange::empty(d.text_range().end()));
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn test_next_node_prev_node() {
+        let root = tree(r#"(0 (3 1:"a" (3 (3) 1:"b") (3)) 2:" " (3 (3 1:"c")) (3))"#);
+        let nodes: Vec<_> = root.descendants().collect();
+        assert_eq!(nodes.len(), 8);
+        for pair in nodes.windows(2) {
+            assert_eq!(pair[0].next_node().as_ref(), Some(&pair[1]));
+            assert_eq!(pair[1].prev_node().as_ref(), Some(&pair[0]));
+        }
+        assert_eq!(root.prev_node(), None);
+        assert_eq!(nodes.last().unwrap().next_node(), None);
+    }
+
+    #[test]
+    fn test_line_text() {
+        let is_newline = |c| c == '\n';
+        let root = tree(r#"(0 1:"a" 2:" " (3 1:"b" 2:"\n  ") 1:"cd" (3 2:" " 1:"e\nf") 2:"\n")"#);
+        let line = |text| token(&root, text).line_text(is_newline).to_string();
+        assert_eq!(line("a"), "a b");
+        assert_eq!(line("b"), "a b");
+        assert_eq!(line("\n  "), "a b");
+        assert_eq!(line("cd"), "  cd e");
+        assert_eq!(line("e\nf"), "  cd e");
+        assert_eq!(line("\n"), "f");
+
+        let root = root.clone_for_update();
+        let cd = token(&root, "cd");
+        cd.detach();
+        assert_eq!(cd.line_text(is_newline), "cd");
+        let ef = token(&root, "e\nf");
+        ef.detach();
+        assert_eq!(ef.line_text(is_newline), "e");
+    }
+
+    #[test]
+    fn test_visit() {
+        let root = tree(r#"(0 (3 1:"a" (3 (3) 1:"b") (3)) 2:" " (3 (3 1:"c")) (3))"#);
+        let mut entered = Vec::new();
+        let mut left = 0;
+        root.visit(|it| match it {
+            WalkEvent::Enter(it) => entered.push((it.kind(), it.text_range())),
+            WalkEvent::Leave(_) => left += 1,
+        });
+        let expected: Vec<_> = root.descendants().map(|it| (it.kind(), it.text_range())).collect();
+        assert_eq!(entered, expected);
+        assert_eq!(left, entered.len());
+    }
+
+    #[test]
+    fn test_contains_kind() {
+        let root = tree(r#"(0 (3 1:"a" (4 5:"b")) 2:" " (6))"#);
+        assert!(root.contains_kind(SyntaxKind(0)));
+        assert!(root.contains_kind(SyntaxKind(5)));
+        assert!(root.contains_kind(SyntaxKind(6)));
+        assert!(!root.contains_kind(SyntaxKind(7)));
+
+        let inner = root.first_child().unwrap();
+        assert!(inner.contains_kind(SyntaxKind(5)));
+        assert!(!inner.contains_kind(WS));
+        let empty = root.last_child().unwrap();
+        assert!(empty.contains_kind(SyntaxKind(6)));
+        assert!(!empty.contains_kind(SyntaxKind(1)));
+
+        let root = root.clone_for_update();
+        root.descendants().find(|it| it.kind() == SyntaxKind(4)).unwrap().detach();
+        assert!(!root.contains_kind(SyntaxKind(5)));
+        assert!(root.contains_kind(SyntaxKind(1)));
+    }
+
+    #[test]
+    fn test_preorder_with_depth() {
+        let root = tree(r#"(0 1:"a" (3 1:"b" (3)) 1:"c")"#);
+        let events = |node: &SyntaxNode<TestLanguage>| -> Vec<_> {
+            node.preorder_with_depth()
+                .map(|(depth, event)| match event {
+                    WalkEvent::Enter(it) => (depth, true, it.kind().0),
+                    WalkEvent::Leave(it) => (depth, false, it.kind().0),
+                })
+                .collect()
+        };
+        assert_eq!(
+            events(&root),
+            [
+                (0, true, 0),
+                (1, true, 1),
+                (1, false, 1),
+                (1, true, 3),
+                (2, true, 1),
+                (2, false, 1),
+                (2, true, 3),
+                (2, false, 3),
+                (1, false, 3),
+                (1, true, 1),
+                (1, false, 1),
+                (0, false, 0),
+            ]
+        );
+
+        let empty = root.descendants().last().unwrap();
+        assert_eq!(events(&empty), [(0, true, 3), (0, false, 3)]);
+    }
+
+    #[test]
+    fn test_range_in_parent() {
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root = tree(r#"(0 1:"ab" (3 2:" " 1:"cd") 1:"e" (3))"#);
+        assert_eq!(root.offset_in_parent(), 0.into());
+        assert_eq!(root.range_in_parent(), range(0, 6));
+
+        let node = root.first_child().unwrap();
+        assert_eq!(node.text_range(), range(2, 5));
+        assert_eq!(node.offset_in_parent(), 2.into());
+        assert_eq!(node.range_in_parent(), range(2, 5));
+        let cd = token(&root, "cd");
+        assert_eq!(cd.text_range(), range(3, 5));
+        assert_eq!(cd.offset_in_parent(), 1.into());
+        assert_eq!(cd.range_in_parent(), range(1, 3));
+        let empty = root.last_child().unwrap();
+        assert_eq!(empty.range_in_parent(), range(6, 6));
+
+        let root = root.clone_for_update();
+        let node = root.first_child().unwrap();
+        let cd = token(&root, "cd");
+        node.detach();
+        assert_eq!(node.range_in_parent(), range(0, 3));
+        assert_eq!(cd.range_in_parent(), range(1, 3));
+        cd.detach();
+        assert_eq!(cd.offset_in_parent(), 0.into());
+        assert_eq!(cd.range_in_parent(), range(0, 2));
+    }
+
+    #[test]
+    fn test_token_with_text() {
+        let root = tree(r#"(0 (3 1:"foo" 2:" ") (3 1:"bar"))"#);
+        let new_root = token(&root, "foo").with_text("quux");
+        assert_eq!(new_root.text(), "quux bar");
+        assert_eq!(token(&new_root, "bar").text_range(), TextRange::new(5.into(), 8.into()));
+        assert!(new_root.last_child().unwrap().green_ptr_eq(&root.last_child().unwrap()));
+        assert!(!new_root.first_child().unwrap().green_ptr_eq(&root.first_child().unwrap()));
+        assert_eq!(root.text(), "foo bar");
+
+        let new_root = token(&root, "bar").with_text("");
+        assert_eq!(new_root.text(), "foo ");
+        assert_eq!(new_root.last_child().unwrap().text_range(), TextRange::empty(4.into()));
+
+        let root = root.clone_for_update();
+        let new_root = token(&root, "foo").with_text("x");
+        assert_eq!(new_root.text(), "x bar");
+        assert_eq!(root.text(), "foo bar");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_token_with_text_detached() {
+        let root = tree(r#"(0 1:"foo")"#).clone_for_update();
+        let foo = token(&root, "foo");
+        foo.detach();
+        foo.with_text("bar");
+    }
+
+    #[test]
+    fn test_has_ancestor_of_kind() {
+        let root = tree(r#"(0 (3 (4 1:"a")) (5))"#);
+        let node = root.descendants().find(|it| it.kind() == SyntaxKind(4)).unwrap();
+        assert!(node.has_ancestor_of_kind(SyntaxKind(4)));
+        assert!(node.has_ancestor_of_kind(SyntaxKind(3)));
+        assert!(node.has_ancestor_of_kind(SyntaxKind(0)));
+        assert!(!node.has_ancestor_of_kind(SyntaxKind(5)));
+        assert!(!node.has_ancestor_of_kind(SyntaxKind(1)));
+        assert!(!root.has_ancestor_of_kind(SyntaxKind(3)));
+
+        let root = root.clone_for_update();
+        let parent = root.first_child().unwrap();
+        let node = parent.first_child().unwrap();
+        parent.detach();
+        assert!(node.has_ancestor_of_kind(SyntaxKind(3)));
+        assert!(!node.has_ancestor_of_kind(SyntaxKind(0)));
+    }
+
+    #[test]
+    fn test_token_covering() {
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root = tree(r#"(0 1:"abc" (3 2:" " 1:"de") (3))"#);
+        let covering = |node: &SyntaxNode<TestLanguage>, start, end| {
+            node.token_covering(range(start, end)).map(|it| it.text().to_string())
+        };
+        assert_eq!(covering(&root, 0, 3).as_deref(), Some("abc"));
+        assert_eq!(covering(&root, 1, 2).as_deref(), Some("abc"));
+        assert_eq!(covering(&root, 1, 1).as_deref(), Some("abc"));
+        assert_eq!(covering(&root, 4, 6).as_deref(), Some("de"));
+        assert_eq!(covering(&root, 2, 5), None);
+        assert_eq!(covering(&root, 0, 6), None);
+
+        let node = root.first_child().unwrap();
+        assert_eq!(covering(&node, 5, 6).as_deref(), Some("de"));
+        assert_eq!(covering(&node, 0, 1), None);
+        let empty = root.last_child().unwrap();
+        assert_eq!(covering(&empty, 6, 6), None);
+    }
+
+    #[test]
+    fn test_zipper() {
+        let root = tree(r#"(0 (3 (4)) (5) 1:"t" (6))"#);
+        let kind = |it: Option<Zipper<TestLanguage>>| it.map(|it| it.focus().kind().0);
+        let zipper = Zipper::new(root.clone());
+        assert_eq!(kind(zipper.up()), None);
+        assert_eq!(kind(zipper.left()), None);
+        assert_eq!(kind(zipper.right()), None);
+
+        let first = zipper.down().unwrap();
+        assert_eq!(first.focus().kind(), SyntaxKind(3));
+        assert_eq!(kind(first.down()), Some(4));
+        assert_eq!(kind(first.down().unwrap().down()), None);
+        assert_eq!(kind(first.down().unwrap().up()), Some(3));
+        assert_eq!(kind(first.left()), None);
+
+        let second = first.right().unwrap();
+        assert_eq!(second.focus().kind(), SyntaxKind(5));
+        assert_eq!(kind(second.down()), None);
+        let last = second.right().unwrap();
+        assert_eq!(last.focus().kind(), SyntaxKind(6));
+        assert_eq!(kind(last.right()), None);
+        assert_eq!(kind(last.left()), Some(5));
+        assert_eq!(last.up().unwrap().into_focus(), root);
+    }
+
+    #[test]
+    fn test_tokens_of_kind() {
+        let root = tree(r#"(0 1:"a" 2:" " (3 1:"b" 4:"s") 1:"c" (3))"#);
+        let texts = |it: &mut dyn Iterator<Item = SyntaxToken<TestLanguage>>| {
+            it.map(|it| it.text().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(texts(&mut root.tokens_of_kind(SyntaxKind(1))), ["a", "b", "c"]);
+        assert_eq!(texts(&mut root.tokens_of_kind(SyntaxKind(5))), Vec::<String>::new());
+        let node = root.first_child().unwrap();
+        assert_eq!(texts(&mut node.tokens_of_kind(SyntaxKind(1))), ["b"]);
+        let empty = root.last_child().unwrap();
+        assert_eq!(texts(&mut empty.tokens_of_kind(SyntaxKind(1))), Vec::<String>::new());
+
+        let kinds = [WS, SyntaxKind(4)];
+        assert_eq!(texts(&mut root.tokens_of_kinds(&kinds)), [" ", "s"]);
+        assert_eq!(texts(&mut root.tokens_of_kinds(&[])), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_indent() {
+        let is_ws = |c| c == ' ' || c == '\t';
+        let root = tree(
+            r#"(0 2:"  " (3 1:"a") 2:"\n  " (3 1:"b" 2:" " (4 1:"c")) 2:"\n\t" (3 1:"d") 1:"e\n" 2:" " (5 1:"g") (5) 1:"h\ni" (5))"#,
+        );
+        let indent = |kind: u16, nth: usize| {
+            let node = root.descendants().filter(|it| it.kind() == SyntaxKind(kind)).nth(nth);
+            node.unwrap().indent(is_ws).to_string()
+        };
+        assert_eq!(root.indent(is_ws), "");
+        assert_eq!(indent(3, 0), "  ");
+        assert_eq!(indent(3, 1), "  ");
+        assert_eq!(indent(4, 0), "  ");
+        assert_eq!(indent(3, 2), "\t");
+        assert_eq!(indent(5, 0), " ");
+        assert_eq!(indent(5, 1), " ");
+        assert_eq!(indent(5, 2), "");
+
+        let root = root.clone_for_update();
+        let node = root.descendants().filter(|it| it.kind() == SyntaxKind(3)).nth(1).unwrap();
+        node.detach();
+        assert_eq!(node.indent(is_ws), "");
+    }
+
+    #[test]
+    fn test_descendants_with_text() {
+        let root = tree(r#"(0 (3 1:"a" 2:" ") (3) 1:"b")"#);
+        let texts: Vec<_> = root
+            .descendants_with_text()
+            .map(|(node, text)| (node.kind().0, text.to_string()))
+            .collect();
+        assert_eq!(texts, [(0, "a b".to_string()), (3, "a ".to_string()), (3, String::new())]);
+
+        let empty = root.last_child().unwrap();
+        let (node, text) = empty.descendants_with_text().next().unwrap();
+        assert_eq!(node, empty);
+        assert_eq!(text, "");
+        assert_eq!(text.to_buf().text_range(), TextRange::empty(2.into()));
+    }
+
+    #[test]
+    fn test_descendants_to_depth() {
+        let root = tree(r#"(0 (3 1:"a" (4 (5))) (3))"#);
+        let kinds =
+            |depth| root.descendants_to_depth(depth).map(|it| it.kind().0).collect::<Vec<_>>();
+        assert_eq!(kinds(0), [0]);
+        assert_eq!(kinds(1), [0, 3, 3]);
+        assert_eq!(kinds(2), [0, 3, 4, 3]);
+        assert_eq!(kinds(3), [0, 3, 4, 5, 3]);
+        assert_eq!(kinds(usize::MAX), root.descendants().map(|it| it.kind().0).collect::<Vec<_>>());
+
+        let empty = root.last_child().unwrap();
+        assert!(empty.descendants_to_depth(5).eq(iter::once(empty.clone())));
+    }
+
+    #[test]
+    fn test_significant_range() {
+        let is_trivia = |kind| kind == WS;
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root = tree(r#"(0 2:" " (3 2:" " 1:"ab" 2:" ") 1:"c" 2:"\n" (3 2:"  ") (3))"#);
+        assert_eq!(root.significant_range(is_trivia), range(2, 6));
+        let nodes: Vec<_> = root.children().collect();
+        assert_eq!(nodes[0].significant_range(is_trivia), range(2, 4));
+        assert_eq!(nodes[1].significant_range(is_trivia), range(7, 9));
+        assert_eq!(nodes[2].significant_range(is_trivia), range(9, 9));
+        assert_eq!(nodes[0].significant_range(|_| false), nodes[0].text_range());
+    }
+
+    #[test]
+    fn test_into_green() {
+        let green = tree(r#"(0 (3 1:"a") 2:" ")"#).into_green();
+        let root: SyntaxNode<TestLanguage> = SyntaxNode::new_root(green.clone());
+        assert!(std::ptr::eq(&*root.clone().into_green(), &*green));
+        assert!(std::ptr::eq(&*root.first_child().unwrap().into_green(), &*green));
+        assert!(std::ptr::eq(&*root.into_green(), &*green));
+
+        let root: SyntaxNode<TestLanguage> = SyntaxNode::new_root(green.clone());
+        let round_trip: SyntaxNode<TestLanguage> = SyntaxNode::new_root(root.clone().into_green());
+        assert_eq!(round_trip.text(), root.text());
+
+        let root = root.clone_for_update();
+        let child = root.first_child().unwrap();
+        child.detach();
+        assert_eq!(child.into_green().to_string(), "a");
+        assert_eq!(root.into_green().to_string(), " ");
+    }
+
+    #[test]
+    fn test_replace_children_with() {
+        let root = tree(r#"(0 (3 1:"a") (3 1:"b"))"#);
+        let second = root.last_child().unwrap();
+        let xy = GreenToken::new(SyntaxKind(1), "xy");
+        let new_root = second.replace_children_with(vec![NodeOrToken::Token(xy)]);
+        assert_eq!(new_root.text(), "axy");
+        assert_eq!(new_root.last_child().unwrap().kind(), SyntaxKind(3));
+        assert!(new_root.first_child().unwrap().green_ptr_eq(&root.first_child().unwrap()));
+        assert_eq!(root.text(), "ab");
+
+        let new_root = second.replace_children_with(Vec::new());
+        assert_eq!(new_root.text(), "a");
+        assert!(new_root.last_child().unwrap().is_empty());
+
+        let new_root = root.replace_children_with(
+            root.last_child().unwrap().green().children().map(|it| it.to_owned()),
+        );
+        assert_eq!(new_root.kind(), SyntaxKind(0));
+        assert_eq!(new_root.text(), "b");
+
+        let root = root.clone_for_update();
+        let new_root = root.first_child().unwrap().replace_children_with(Vec::new());
+        assert_eq!(new_root.text(), "b");
+        assert_eq!(root.text(), "ab");
+    }
+
+    #[test]
+    fn test_text_with_replacement() {
+        let root = tree(r#"(0 (3 1:"let" 2:" " 1:"x") 2:" " 1:"y")"#);
+        let x = token(&root, "x");
+        assert_eq!(root.text_with_replacement(&x, "foo"), "let foo y");
+        assert_eq!(root.text_with_replacement(&x, ""), "let  y");
+        assert_eq!(root.text(), "let x y");
+
+        let node = root.first_child().unwrap();
+        assert_eq!(node.text_with_replacement(&x, "z"), "let z");
+        let y = token(&root, "y");
+        assert_eq!(node.text_with_replacement(&y, "z"), "let x");
+
+        let root = root.clone_for_update();
+        let x = token(&root, "x");
+        token(&root, "let").detach();
+        assert_eq!(root.text_with_replacement(&x, "z"), " z y");
+    }
+
+    #[test]
+    fn test_text_to_string() {
+        let root = tree(r#"(0 (3 1:"a" 2:" ") (3) 1:"bé")"#);
+        let text = root.text_to_string();
+        assert_eq!(text, "a bé");
+        assert_eq!(text.capacity(), text.len());
+        assert_eq!(root.first_child().unwrap().text_to_string(), "a ");
+        assert_eq!(root.children().nth(1).unwrap().text_to_string(), "");
+
+        let root = root.clone_for_update();
+        root.first_child().unwrap().detach();
+        assert_eq!(root.text_to_string(), "bé");
+    }
+
+    #[test]
+    fn test_is_first_child_is_last_child() {
+        let root = tree(r#"(0 (3 (4)) 1:"a" (5) 1:"b" (6))"#);
+        let nodes: Vec<_> = root.children().collect();
+        assert!(nodes[0].is_first_child());
+        assert!(!nodes[0].is_last_child());
+        assert!(!nodes[1].is_first_child());
+        assert!(!nodes[1].is_last_child());
+        assert!(!nodes[2].is_first_child());
+        assert!(nodes[2].is_last_child());
+
+        let only = nodes[0].first_child().unwrap();
+        assert!(only.is_first_child());
+        assert!(only.is_last_child());
+        assert!(!root.is_first_child());
+        assert!(!root.is_last_child());
+
+        let root = root.clone_for_update();
+        let first = root.first_child().unwrap();
+        first.detach();
+        assert!(!first.is_first_child());
+        assert!(!first.is_last_child());
+        assert!(!root.first_child().unwrap().is_first_child());
+        assert!(root.last_child().unwrap().is_last_child());
+        token(&root, "b").detach();
+        assert!(root.last_child().unwrap().is_last_child());
+    }
+
+    #[test]
+    fn test_self_and_siblings() {
+        let root = tree(r#"(0 1:"a" (3) 2:" " (4) 1:"b")"#);
+        let kinds = |it: &mut dyn Iterator<Item = SyntaxElement<TestLanguage>>| {
+            it.map(|it| it.kind().0).collect::<Vec<_>>()
+        };
+        let first = root.first_child().unwrap();
+        let last = root.last_child().unwrap();
+        assert_eq!(kinds(&mut first.self_and_following_siblings()), [3, 2, 4, 1]);
+        assert_eq!(kinds(&mut first.self_and_preceding_siblings()), [3, 1]);
+        assert_eq!(kinds(&mut last.self_and_following_siblings()), [4, 1]);
+        assert_eq!(kinds(&mut last.self_and_preceding_siblings()), [4, 2, 3, 1]);
+        assert_eq!(kinds(&mut root.self_and_following_siblings()), [0]);
+        assert_eq!(kinds(&mut root.self_and_preceding_siblings()), [0]);
+    }
+
+    #[test]
+    fn test_ancestor_of() {
+        #[derive(Debug, PartialEq)]
+        struct Block(SyntaxNode<TestLanguage>);
+
+        impl AstNode for Block {
+            type Language = TestLanguage;
+
+            fn can_cast(kind: SyntaxKind) -> bool {
+                kind == SyntaxKind(3)
+            }
+            fn cast(node: SyntaxNode<TestLanguage>) -> Option<Block> {
+                Self::can_cast(node.kind()).then(|| Block(node))
+            }
+            fn syntax(&self) -> &SyntaxNode<TestLanguage> {
+                &self.0
+            }
+        }
+
+        let root = tree(r#"(0 (3 (4 (3 (5)))) (4))"#);
+        let nodes: Vec<_> = root.descendants().collect();
+        let (outer, inner, leaf) = (&nodes[1], &nodes[3], &nodes[4]);
+        assert_eq!(leaf.ancestor_of::<Block>(), Some(Block(inner.clone())));
+        assert_eq!(leaf.self_or_ancestor_of::<Block>(), Some(Block(inner.clone())));
+        assert_eq!(inner.ancestor_of::<Block>(), Some(Block(outer.clone())));
+        assert_eq!(inner.self_or_ancestor_of::<Block>(), Some(Block(inner.clone())));
+        assert_eq!(outer.ancestor_of::<Block>(), None);
+        assert_eq!(root.ancestor_of::<Block>(), None);
+        assert_eq!(root.self_or_ancestor_of::<Block>(), None);
+        assert_eq!(nodes[5].ancestor_of::<Block>(), None);
+    }
+
+    #[test]
+    fn test_matching_delimiter() {
+        const L: SyntaxKind = SyntaxKind(4);
+        const R: SyntaxKind = SyntaxKind(5);
+        let root = tree(r#"(0 4:"{" (3 1:"a" 4:"[" (3 4:"(" 5:")") 5:"]") 5:"}" 4:"<" 1:"b")"#);
+        let matching = |text| token(&root, text).matching_delimiter(L, R).map(|it| it.text_range());
+
+        assert_eq!(matching("{"), Some(token(&root, "}").text_range()));
+        assert_eq!(matching("}"), Some(token(&root, "{").text_range()));
+        assert_eq!(matching("["), Some(token(&root, "]").text_range()));
+        assert_eq!(matching("]"), Some(token(&root, "[").text_range()));
+        assert_eq!(matching("("), Some(token(&root, ")").text_range()));
+        assert_eq!(matching(")"), Some(token(&root, "(").text_range()));
+        assert_eq!(matching("<"), None);
+        assert_eq!(matching("a"), None);
+
+        let root = root.clone_for_update();
+        let open = token(&root, "{");
+        token(&root, "}").detach();
+        assert_eq!(open.matching_delimiter(L, R).map(|it| it.text().to_string()).as_deref(), None);
+        open.detach();
+        assert_eq!(open.matching_delimiter(L, R), None);
+    }
+
+    #[test]
+    fn test_green_ptr_eq() {
+        let root = tree(r#"(0 (3 1:"a") (3 1:"a"))"#);
+        let nodes: Vec<_> = root.children().collect();
+        assert!(nodes[0].green_ptr_eq(&nodes[0]));
+        assert!(!nodes[0].green_ptr_eq(&nodes[1]));
+        assert!(nodes[0].content_eq(&nodes[1]));
+
+        let edited = token(&nodes[1], "a").with_text("b");
+        assert!(edited.first_child().unwrap().green_ptr_eq(&nodes[0]));
+        assert!(!edited.last_child().unwrap().green_ptr_eq(&nodes[1]));
+        assert!(!edited.green_ptr_eq(&root));
+
+        let same = SyntaxNode::<TestLanguage>::new_root(root.green().into_owned());
+        assert!(same.green_ptr_eq(&root));
+    }
+
+    #[test]
+    fn test_children_marking_last() {
+        let root = tree(r#"(0 (3) 1:"," (4) 1:",")"#);
+        let marks: Vec<_> =
+            root.children_marking_last().map(|(it, last)| (it.kind().0, last)).collect();
+        assert_eq!(marks, [(3, false), (4, true)]);
+        let marks: Vec<_> = root
+            .children_with_tokens_marking_last()
+            .map(|(it, last)| (it.kind().0, last))
+            .collect();
+        assert_eq!(marks, [(3, false), (1, false), (4, false), (1, true)]);
+
+        let single = tree(r#"(0 1:"a" (3))"#);
+        let marks: Vec<_> =
+            single.children_marking_last().map(|(it, last)| (it.kind().0, last)).collect();
+        assert_eq!(marks, [(3, true)]);
+        let empty = single.first_child().unwrap();
+        assert_eq!(empty.children_marking_last().count(), 0);
+        assert_eq!(empty.children_with_tokens_marking_last().count(), 0);
+    }
+
+    #[test]
+    fn test_elements_covering_exactly() {
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root = tree(r#"(0 1:"a" (3 1:"bc") 2:" " (3) 1:"d")"#);
+        let covering = |start, end| {
+            root.elements_covering_exactly(range(start, end))
+                .into_iter()
+                .map(|it| it.kind().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(covering(0, 5), [1, 3, 2, 3, 1]);
+        assert_eq!(covering(0, 1), [1]);
+        assert_eq!(covering(1, 3), [3]);
+        assert_eq!(covering(1, 4), [3, 2]);
+        assert_eq!(covering(3, 5), [2, 3, 1]);
+        assert_eq!(covering(4, 5), [1]);
+        assert_eq!(covering(1, 2), Vec::<u16>::new());
+        assert_eq!(covering(0, 2), Vec::<u16>::new());
+        assert_eq!(covering(2, 2), Vec::<u16>::new());
+        assert_eq!(covering(4, 4), Vec::<u16>::new());
+
+        let node = root.first_child().unwrap();
+        assert_eq!(node.elements_covering_exactly(range(1, 3)).len(), 1);
+        assert_eq!(node.elements_covering_exactly(range(0, 3)).len(), 0);
+    }
+
+    #[test]
+    fn test_meaningful_token_at() {
+        let is_trivia = |kind| kind == WS;
+        let root = tree(r#"(0 1:"ab" 2:" " 1:"c" 2:"   " (3 2:"  " 1:"d") 2:" ")"#);
+        let at = |offset: u32| {
+            root.meaningful_token_at(offset.into(), is_trivia).map(|it| it.text().to_string())
+        };
+        assert_eq!(at(0).as_deref(), Some("ab"));
+        assert_eq!(at(1).as_deref(), Some("ab"));
+        assert_eq!(at(2).as_deref(), Some("ab"));
+        assert_eq!(at(3).as_deref(), Some("c"));
+        assert_eq!(at(4).as_deref(), Some("c"));
+        assert_eq!(at(5).as_deref(), Some("c"));
+        assert_eq!(at(6).as_deref(), Some("c"));
+        assert_eq!(at(7).as_deref(), Some("d"));
+        assert_eq!(at(10).as_deref(), Some("d"));
+        assert_eq!(at(11).as_deref(), Some("d"));
+
+        let node = root.first_child().unwrap();
+        let at = |offset: u32| {
+            node.meaningful_token_at(offset.into(), is_trivia).map(|it| it.text().to_string())
+        };
+        assert_eq!(at(7).as_deref(), Some("d"));
+        let only_trivia = tree(r#"(0 2:" " (3))"#);
+        assert_eq!(only_trivia.meaningful_token_at(0.into(), is_trivia), None);
+        let empty = only_trivia.last_child().unwrap();
+        assert_eq!(empty.meaningful_token_at(1.into(), is_trivia), None);
+    }
+
+    #[test]
+    fn test_nearest_boundary() {
+        let root = tree(r#"(0 1:"abcd" (3 1:"efg") (3) 1:"h")"#);
+        let nearest = |node: &SyntaxNode<TestLanguage>, offset: u32| {
+            u32::from(node.nearest_boundary(offset.into()))
+        };
+        assert_eq!(nearest(&root, 0), 0);
+        assert_eq!(nearest(&root, 1), 0);
+        assert_eq!(nearest(&root, 2), 0);
+        assert_eq!(nearest(&root, 3), 4);
+        assert_eq!(nearest(&root, 4), 4);
+        assert_eq!(nearest(&root, 6), 7);
+        assert_eq!(nearest(&root, 8), 8);
+        assert_eq!(nearest(&root, 100), 8);
+
+        let node = root.first_child().unwrap();
+        assert_eq!(nearest(&node, 0), 4);
+        assert_eq!(nearest(&node, 5), 4);
+        assert_eq!(nearest(&node, 100), 7);
+        let empty = root.children().nth(1).unwrap();
+        assert_eq!(nearest(&empty, 0), 7);
+    }
+
+    #[test]
+    fn test_elements_at_offsets() {
+        let root = tree(r#"(0 1:"ab" (3 2:" " (3) 1:"cd") 1:"e")"#);
+        let at = |node: &SyntaxNode<TestLanguage>, offsets: &[u32]| {
+            let offsets: Vec<TextSize> = offsets.iter().map(|&it| it.into()).collect();
+            node.elements_at_offsets(&offsets)
+                .into_iter()
+                .map(|it| match it {
+                    NodeOrToken::Node(it) => it.text().to_string(),
+                    NodeOrToken::Token(it) => it.text().to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let offsets = [0, 1, 1, 2, 3, 4, 5, 6];
+        let expected: Vec<_> = offsets
+            .iter()
+            .map(|&it| match root.token_at_offset(it.into()) {
+                TokenAtOffset::Single(it) | TokenAtOffset::Between(_, it) => it.text().to_string(),
+                TokenAtOffset::None => unreachable!(),
+            })
+            .collect();
+        assert_eq!(at(&root, &offsets[..offsets.len() - 1]), expected[..offsets.len() - 1]);
+        assert_eq!(at(&root, &offsets), ["ab", "ab", "ab", " ", "cd", "cd", "e", "e"]);
+        assert_eq!(at(&root, &[]), Vec::<String>::new());
+        assert_eq!(at(&root, &[6, 6]), ["e", "e"]);
+
+        let node = root.first_child().unwrap();
+        assert_eq!(at(&node, &[2, 3, 5]), [" ", "cd", "cd"]);
+        let empty = node.first_child().unwrap();
+        assert_eq!(at(&empty, &[3, 3]), ["", ""]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_elements_at_offsets_out_of_range() {
+        let root = tree(r#"(0 1:"ab" (3 1:"c"))"#);
+        root.first_child().unwrap().elements_at_offsets(&[1.into()]);
+    }
+
+    #[test]
+    fn test_trivia_gaps() {
+        let is_trivia = |kind| kind == WS;
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root =
+            tree(r#"(0 2:" " 1:"a" 2:" " (3 2:"\n" 1:"b") 2:"" 1:"c" (3) 2:" " (3 2:"  "))"#);
+        let gaps: Vec<_> = root.trivia_gaps(is_trivia).collect();
+        assert_eq!(gaps, [range(0, 1), range(2, 4), range(5, 5), range(6, 9)]);
+
+        let node = root.first_child().unwrap();
+        assert_eq!(node.trivia_gaps(is_trivia).collect::<Vec<_>>(), [range(3, 4)]);
+        let empty = root.children().nth(1).unwrap();
+        assert_eq!(empty.trivia_gaps(is_trivia).count(), 0);
+        assert_eq!(root.trivia_gaps(|_| false).count(), 0);
+        assert_eq!(root.trivia_gaps(|_| true).collect::<Vec<_>>(), [root.text_range()]);
+    }
+
+    #[test]
+    fn test_highlight_ranges() {
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let root = tree(r#"(0 1:"ab" 2:" " (3 4:"cd" 2:" " (3 1:"e")) (3) 4:"fg")"#);
+        let kinds = [SyntaxKind(1), SyntaxKind(4)];
+        let highlight = |node: &SyntaxNode<TestLanguage>, start, end| {
+            node.highlight_ranges(range(start, end), &kinds)
+                .into_iter()
+                .map(|(range, kind)| (u32::from(range.start())..u32::from(range.end()), kind.0))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(highlight(&root, 0, 9), [(0..2, 1), (3..5, 4), (6..7, 1), (7..9, 4)]);
+        assert_eq!(highlight(&root, 1, 4), [(0..2, 1), (3..5, 4)]);
+        assert_eq!(highlight(&root, 2, 3), []);
+        assert_eq!(highlight(&root, 5, 6), []);
+        assert_eq!(highlight(&root, 7, 7), []);
+        assert_eq!(root.highlight_ranges(root.text_range(), &[]), []);
+
+        let node = root.first_child().unwrap();
+        assert_eq!(highlight(&node, 0, 9), [(3..5, 4), (6..7, 1)]);
+        let empty = root.children().nth(1).unwrap();
+        assert_eq!(highlight(&empty, 0, 9), []);
+    }
+
+    #[test]
+    fn test_graft() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        use crate::ast::SyntaxNodePtr;
+
+        let root = tree(r#"(0 (3 (4 (5 1:"ab"))) (6) 1:"c" (7 (8) 1:"d" (9)) (10))"#);
+        let green = root.green();
+        for node in root.descendants() {
+            let ptr = SyntaxNodePtr::new(&node);
+            let replacement = GreenNode::new(node.kind(), vec![GreenToken::new(WS, "xyz").into()]);
+            // `to_node` panics for the empty nodes it can't find, and then
+            // `graft` must fail as well.
+            let resolved = panic::catch_unwind(AssertUnwindSafe(|| ptr.to_node(&root)));
+            let expected = resolved.ok().map(|it| it.replace_with(replacement.clone()));
+            assert_eq!(green.graft(&ptr, replacement), expected, "{:?}", node);
+        }
+
+        let other = tree(r#"(0 1:"a" (3 1:"bc") (6))"#);
+        let replacement = GreenNode::new(SyntaxKind(3), vec![]);
+        for node in other.descendants().skip(1) {
+            assert_eq!(green.graft(&SyntaxNodePtr::new(&node), replacement.clone()), None);
+        }
+    }
+
+    #[test]
+    fn test_node_id() {
+        let assert_ids_match_eq = |root: &SyntaxNode<TestLanguage>| {
+            let nodes: Vec<_> = root.descendants().collect();
+            for a in &nodes {
+                for b in &nodes {
+                    assert_eq!(a.node_id() == b.node_id(), a == b, "{:?} {:?}", a, b);
+                }
+            }
+        };
+
+        // The first two children share one green node, and the nested nodes
+        // share one range.
+        let shared = tree(r#"(3 1:"a")"#).into_green();
+        let rest = tree(r#"(0 (4 (5 1:"b")) (6) (6))"#).into_green();
+        let mut children: Vec<NodeOrToken<GreenNode, GreenToken>> =
+            vec![shared.clone().into(), shared.into()];
+        children.extend(rest.children().map(|it| it.to_owned()));
+        let root = SyntaxNode::new_root(GreenNode::new(SyntaxKind(0), children));
+        assert_ids_match_eq(&root);
+        let nodes: Vec<_> = root.children().collect();
+        assert!(nodes[0].green_ptr_eq(&nodes[1]));
+        assert_ne!(nodes[0].node_id(), nodes[1].node_id());
+        assert_eq!(nodes[2].node_id(), root.children().nth(2).unwrap().node_id());
+        assert_eq!(nodes[0].node_id(), token(&root, "a").parent().unwrap().node_id());
+
+        let root = root.clone_for_update();
+        assert_ids_match_eq(&root);
+        let node = root.children().nth(2).unwrap();
+        let id = node.node_id();
+        let space = tree(r#"(0 2:" ")"#).clone_for_update().first_token().unwrap();
+        space.detach();
+        root.splice_children(0..0, vec![space.into()]);
+        assert_ne!(node.node_id(), id);
+        assert_eq!(node.node_id(), root.children().nth(2).unwrap().node_id());
+        assert_ids_match_eq(&root);
+    }
+
+    #[test]
+    fn test_kind_table() {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        enum Sparse {}
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        enum Kind {
+            A = 1,
+            B = 4,
+            C = 5,
+            Unlisted = 7,
+        }
+
+        impl Language for Sparse {
+            type Kind = Kind;
+
+            fn kind_from_raw(raw: SyntaxKind) -> Kind {
+                match raw.0 {
+                    1 => Kind::A,
+                    4 => Kind::B,
+                    5 => Kind::C,
+                    _ => Kind::Unlisted,
+                }
+            }
+            fn kind_to_raw(kind: Kind) -> SyntaxKind {
+                SyntaxKind(kind as u16)
+            }
+            fn all_kinds() -> &'static [Kind] {
+                &[Kind::C, Kind::A, Kind::B]
+            }
+        }
+
+        let table = KindTable::<Sparse, _>::new(|kind| format!("{:?}", kind));
+        assert_eq!(table.get(Kind::A).map(String::as_str), Some("A"));
+        assert_eq!(table.get(Kind::B).map(String::as_str), Some("B"));
+        assert_eq!(table.get(Kind::C).map(String::as_str), Some("C"));
+        assert_eq!(table.get(Kind::Unlisted), None);
+        // The gaps between listed raw kinds are empty.
+        assert_eq!(table.values.len(), 6);
+        assert_eq!(table.values.iter().filter(|it| it.is_none()).count(), 3);
+
+        let mut calls = 0;
+        let table = KindTable::<TestLanguage, _>::new(|_| calls += 1);
+        assert_eq!(calls, 0);
+        assert!(table.values.is_empty());
+        assert_eq!(table.get(SyntaxKind(0)), None);
+        assert_eq!(TestLanguage::all_kinds(), &[]);
+    }
+
+    #[test]
+    fn test_line_col_range() {
+        let root = tree(r#"(0 1:"é" 2:"\n" (3 1:"😀a" 2:"\n" 1:"bc") 2:"\n" (4))"#);
+        let line_index = LineIndex::new(&root.text().to_string());
+        let line_col = |line, col| LineCol { line, col };
+
+        let node = root.first_child().unwrap();
+        assert_eq!(node.line_col_range(&line_index), (line_col(1, 0), line_col(2, 2)));
+        assert_eq!(root.line_col_range(&line_index), (line_col(0, 0), line_col(3, 0)));
+        let empty = root.last_child().unwrap();
+        assert_eq!(empty.line_col_range(&line_index), (line_col(3, 0), line_col(3, 0)));
+
+        let inner = tree(r#"(0 1:"😀" (3 1:"é"))"#);
+        let line_index = LineIndex::new(&inner.text().to_string());
+        let node = inner.first_child().unwrap();
+        assert_eq!(node.line_col_range(&line_index), (line_col(0, 2), line_col(0, 3)));
+    }
+
+    #[test]
+    fn test_descendants_rev() {
+        let root = tree(r#"(0 (3 1:"a" (4 (5))) (6 (7) 1:"b" (8 (9))) (10))"#);
+        let rev = |node: &SyntaxNode<TestLanguage>| {
+            let mut expected: Vec<_> = node.descendants().collect();
+            expected.reverse();
+            assert_eq!(node.descendants_rev().collect::<Vec<_>>(), expected);
+        };
+        rev(&root);
+        for node in root.descendants() {
+            rev(&node);
+        }
+    }
+
+    #[test]
+    fn test_child_count() {
+        let root = tree(r#"(0 1:"a" (3 1:"b") 2:" " (4) (3 (5)) 1:"c")"#);
+        for node in root.descendants() {
+            assert_eq!(node.child_count(), node.children().count());
+            assert_eq!(node.token_inclusive_child_count(), node.children_with_tokens().count());
+        }
+        assert_eq!(root.child_count(), 3);
+        assert_eq!(root.token_inclusive_child_count(), 6);
+        let empty = root.children().nth(1).unwrap();
+        assert_eq!(empty.child_count(), 0);
+        assert_eq!(empty.token_inclusive_child_count(), 0);
+    }
+
+    #[test]
+    fn test_ast_list() {
+        use crate::ast::{support, AstList};
+
+        const COMMA: SyntaxKind = SyntaxKind(5);
+
+        #[derive(Debug, PartialEq)]
+        struct Item(SyntaxNode<TestLanguage>);
+
+        impl AstNode for Item {
+            type Language = TestLanguage;
+
+            fn can_cast(kind: SyntaxKind) -> bool {
+                kind == SyntaxKind(3)
+            }
+            fn cast(node: SyntaxNode<TestLanguage>) -> Option<Item> {
+                Self::can_cast(node.kind()).then(|| Item(node))
+            }
+            fn syntax(&self) -> &SyntaxNode<TestLanguage> {
+                &self.0
+            }
+        }
+
+        let root = tree(r#"(0 5:"," (3 1:"a") 5:"," 2:" " (4 1:"x") (3 1:"b") 5:"," (3) 5:",")"#);
+        let list: AstList<Item> = support::list(&root, COMMA);
+        assert_eq!(list.syntax(), &root);
+        assert_eq!(list.len(), 3);
+        assert!(!list.is_empty());
+        let texts: Vec<_> = list.iter().map(|it| it.0.text().to_string()).collect();
+        assert_eq!(texts, ["a", "b", ""]);
+        assert_eq!(list.get(0).unwrap().0.text(), "a");
+        assert_eq!(list.get(1).unwrap().0.text(), "b");
+        assert_eq!(list.get(2).unwrap().0.kind(), SyntaxKind(3));
+        assert_eq!(list.get(3), None);
+        assert_eq!((&list).into_iter().count(), 3);
+        let separators: Vec<_> =
+            list.separators().map(|it| u32::from(it.text_range().start())).collect();
+        assert_eq!(separators, [0, 2, 6, 7]);
+
+        let no_items = tree(r#"(0 5:"," (4 1:"x"))"#);
+        let list: AstList<Item> = AstList::new(no_items, COMMA);
+        assert_eq!(list.len(), 0);
+        assert!(list.is_empty());
+        assert_eq!(list.get(0), None);
+        assert_eq!(list.separators().count(), 1);
+    }
+}
@@ -20,7 +20,7 @@ use std::{
     marker::PhantomData,
 };
 
-use crate::{Language, SyntaxNode, SyntaxNodeChildren, TextRange};
+use crate::{Language, SyntaxNode, SyntaxNodeChildren, SyntaxToken, TextRange};
 
 /// The main trait to go from untyped [`SyntaxNode`] to a typed AST. The
 /// conversion itself has zero runtime cost: AST and syntax nodes have exactly
@@ -99,6 +99,10 @@ impl<L: Language> SyntaxNodePtr<L> {
     pub fn kind(&self) -> L::Kind {
         self.kind
     }
+
+    pub(crate) fn text_range(&self) -> TextRange {
+        self.range
+    }
 }
 
 /// Like [`SyntaxNodePtr`], but remembers the type of node.
@@ -183,10 +187,77 @@ impl<N: AstNode> Iterator for AstChildren<N> {
     }
 }
 
+/// The elements of a list node, such as an argument list, which are separated
+/// by tokens of the given kind.
+#[derive(Debug, Clone)]
+pub struct AstList<N: AstNode> {
+    parent: SyntaxNode<N::Language>,
+    separator: <N::Language as Language>::Kind,
+    ph: PhantomData<N>,
+}
+
+impl<N: AstNode> AstList<N> {
+    pub fn new(
+        parent: SyntaxNode<N::Language>,
+        separator: <N::Language as Language>::Kind,
+    ) -> Self {
+        AstList { parent, separator, ph: PhantomData }
+    }
+
+    /// Returns the list node itself.
+    pub fn syntax(&self) -> &SyntaxNode<N::Language> {
+        &self.parent
+    }
+
+    /// Returns the elements of the list.
+    pub fn iter(&self) -> AstChildren<N> {
+        AstChildren::new(&self.parent)
+    }
+
+    /// Returns the number of elements, which is linear in the number of
+    /// children.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns the element with the given index.
+    pub fn get(&self, index: usize) -> Option<N> {
+        self.iter().nth(index)
+    }
+
+    /// Returns the separator tokens of the list.
+    pub fn separators(&self) -> impl Iterator<Item = SyntaxToken<N::Language>> {
+        let separator = self.separator;
+        self.parent
+            .children_with_tokens()
+            .filter_map(|it| it.into_token())
+            .filter(move |it| it.kind() == separator)
+    }
+}
+
+impl<N: AstNode> IntoIterator for &AstList<N> {
+    type Item = N;
+    type IntoIter = AstChildren<N>;
+    fn into_iter(self) -> AstChildren<N> {
+        self.iter()
+    }
+}
+
 pub mod support {
-    use super::{AstChildren, AstNode};
+    use super::{AstChildren, AstList, AstNode};
     use crate::{Language, SyntaxNode, SyntaxToken};
 
+    pub fn list<N: AstNode>(
+        parent: &SyntaxNode<N::Language>,
+        separator: <N::Language as Language>::Kind,
+    ) -> AstList<N> {
+        AstList::new(parent.clone(), separator)
+    }
+
     pub fn child<N: AstNode>(parent: &SyntaxNode<N::Language>) -> Option<N> {
         parent.children().find_map(N::cast)
     }
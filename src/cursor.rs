@@ -95,7 +95,7 @@ use std::{
 use countme::Count;
 
 use crate::{
-    green::{GreenChild, GreenElementRef, GreenNodeData, GreenTokenData, SyntaxKind},
+    green::{node_hash, GreenChild, GreenElementRef, GreenNodeData, GreenTokenData, SyntaxKind},
     sll,
     utility_types::Delta,
     Direction, GreenNode, GreenToken, NodeOrToken, SyntaxText, TextRange, TextSize, TokenAtOffset,
@@ -120,7 +120,7 @@ struct NodeData {
     /// Invariant: never changes after NodeData is created.
     mutable: bool,
     /// Absolute offset for immutable nodes, unused for mutable nodes.
-    offset: TextSize,
+    offset: Cell<TextSize>,
     // The following links only have meaning when `mutable` is true.
     first: Cell<*const NodeData>,
     /// Invariant: never null if mutable.
@@ -164,6 +164,23 @@ impl Drop for SyntaxNode {
     }
 }
 
+/// A cheap identifier of a [`SyntaxNode`], see [`SyntaxNode::node_id`].
+///
+/// Two nodes have the same id if and only if they compare equal. The id is
+/// only meaningful while the tree is alive: once it is dropped, the memory
+/// of its green nodes may be reused by another tree.
+///
+/// In an immutable tree, the id of a node never changes. In a mutable tree,
+/// ids are only stable between edits, as the id is derived from the green
+/// node and the offset, which change when the node or one of its
+/// descendants is edited, or when elements before it are inserted or
+/// removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    green: usize,
+    offset: TextSize,
+}
+
 #[derive(Debug)]
 pub struct SyntaxToken {
     ptr: ptr::NonNull<NodeData>,
@@ -237,7 +254,7 @@ impl NodeData {
             green,
 
             mutable,
-            offset,
+            offset: Cell::new(offset),
             first: Cell::new(ptr::null()),
             next: Cell::new(ptr::null()),
             prev: Cell::new(ptr::null()),
@@ -317,6 +334,17 @@ impl NodeData {
         self.parent.get().map(|it| unsafe { &*it.as_ptr() })
     }
 
+    fn root(&self) -> &NodeData {
+        iter::successors(Some(self), |it| it.parent()).last().unwrap()
+    }
+
+    fn root_node(&self) -> SyntaxNode {
+        let root = self.root();
+        assert!(matches!(root.green, Green::Node { .. }), "the root is a token");
+        root.inc_rc();
+        SyntaxNode { ptr: ptr::NonNull::from(root) }
+    }
+
     #[inline]
     fn green(&self) -> GreenElementRef<'_> {
         match &self.green {
@@ -345,7 +373,7 @@ impl NodeData {
         if self.mutable {
             self.offset_mut()
         } else {
-            self.offset
+            self.offset.get()
         }
     }
 
@@ -363,6 +391,14 @@ impl NodeData {
         res
     }
 
+    #[inline]
+    fn offset_in_parent(&self) -> TextSize {
+        match self.green_siblings().nth(self.index() as usize) {
+            Some(child) => child.rel_offset(),
+            None => 0.into(),
+        }
+    }
+
     #[inline]
     fn text_range(&self) -> TextRange {
         let offset = self.offset();
@@ -388,9 +424,22 @@ impl NodeData {
             })
         })
     }
+    fn next_sibling_by_kind(&self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        let mut siblings = self.green_siblings().enumerate();
+        let index = self.index() as usize;
+
+        siblings.nth(index);
+        siblings.find_map(|(index, child)| {
+            child.as_ref().into_node().filter(|green| green.kind() == kind).and_then(|green| {
+                let parent = self.parent_node()?;
+                let offset = parent.offset() + child.rel_offset();
+                Some(SyntaxNode::new_child(green, parent, index as u32, offset))
+            })
+        })
+    }
     fn prev_sibling(&self) -> Option<SyntaxNode> {
         let mut rev_siblings = self.green_siblings().enumerate().rev();
-        let index = rev_siblings.len() - (self.index() as usize);
+        let index = rev_siblings.len().checked_sub(self.index() as usize + 1)?;
 
         rev_siblings.nth(index);
         rev_siblings.find_map(|(index, child)| {
@@ -555,6 +604,22 @@ impl SyntaxNode {
         SyntaxNode::new_root(self.green().into())
     }
 
+    pub fn into_green(self) -> GreenNode {
+        let root = iter::successors(Some(self), SyntaxNode::parent).last().unwrap();
+        if root.data().rc.get() != 1 {
+            return root.green_ref().to_owned();
+        }
+        // This is the last reference to the tree, so it owns the green node.
+        // Steal it instead of bumping the refcount!
+        let root = ManuallyDrop::new(root);
+        let data = unsafe { Box::from_raw(root.ptr.as_ptr()) };
+        debug_assert!(data.parent.get().is_none() && data.first.get().is_null());
+        match &data.green {
+            Green::Node { ptr } => unsafe { GreenNode::from_raw(ptr.get()) },
+            Green::Token { .. } => unreachable!(),
+        }
+    }
+
     #[inline]
     fn data(&self) -> &NodeData {
         unsafe { self.ptr.as_ref() }
@@ -593,11 +658,49 @@ impl SyntaxNode {
         self.data().index() as usize
     }
 
+    pub fn is_first_child(&self) -> bool {
+        self.data().parent().is_some() && self.index() == 0
+    }
+
+    pub fn is_last_child(&self) -> bool {
+        self.data().parent().is_some() && self.index() + 1 == self.data().green_siblings().len()
+    }
+
+    #[inline]
+    pub fn offset_in_parent(&self) -> TextSize {
+        self.data().offset_in_parent()
+    }
+
+    #[inline]
+    pub fn range_in_parent(&self) -> TextRange {
+        TextRange::at(self.offset_in_parent(), self.data().green().text_len())
+    }
+
     #[inline]
     pub fn text(&self) -> SyntaxText {
         SyntaxText::new(self.clone())
     }
 
+    #[inline]
+    pub fn child_count(&self) -> usize {
+        self.green_ref().children().filter(|it| it.as_node().is_some()).count()
+    }
+
+    #[inline]
+    pub fn token_inclusive_child_count(&self) -> usize {
+        self.green_ref().children().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.green_ref().is_empty()
+    }
+
+    #[inline]
+    pub fn has_text(&self) -> bool {
+        self.green_ref().has_text()
+    }
+
     #[inline]
     pub fn green(&self) -> Cow<'_, GreenNodeData> {
         let green_ref = self.green_ref();
@@ -611,6 +714,19 @@ impl SyntaxNode {
         self.data().green().into_node().unwrap()
     }
 
+    pub fn content_hash(&self) -> u64 {
+        node_hash(self.green_ref())
+    }
+
+    pub fn content_eq(&self, other: &SyntaxNode) -> bool {
+        self.green_ref() == other.green_ref()
+    }
+
+    #[inline]
+    pub fn green_ptr_eq(&self, other: &SyntaxNode) -> bool {
+        ptr::eq(self.green_ref(), other.green_ref())
+    }
+
     #[inline]
     pub fn parent(&self) -> Option<SyntaxNode> {
         self.data().parent_node()
@@ -621,11 +737,55 @@ impl SyntaxNode {
         iter::successors(Some(self.clone()), SyntaxNode::parent)
     }
 
+    #[inline]
+    pub fn root(&self) -> SyntaxNode {
+        self.data().root_node()
+    }
+
+    #[inline]
+    pub fn is_root(&self) -> bool {
+        self.data().parent().is_none()
+    }
+
+    /// Returns the identity of this node, a `Copy` key for maps of data
+    /// attached to the nodes of a tree. In a mutable tree, the id of a node
+    /// changes with every edit which changes its green node or offset, see
+    /// [`NodeId`].
+    #[inline]
+    pub fn node_id(&self) -> NodeId {
+        let (green, offset) = self.data().key();
+        NodeId { green: green.as_ptr() as usize, offset }
+    }
+
+    pub fn has_ancestor_of_kind(&self, kind: SyntaxKind) -> bool {
+        iter::successors(Some(self.data()), |data| data.parent()).any(|data| data.kind() == kind)
+    }
+
+    pub fn is_descendant_of(&self, ancestor: &SyntaxNode) -> bool {
+        if !ancestor.text_range().contains_range(self.text_range()) {
+            return false;
+        }
+        // Different trees have different roots, walking up to the roots
+        // doesn't create any cursors.
+        if self.data().root().key() != ancestor.data().root().key() {
+            return false;
+        }
+        let key = ancestor.data().key();
+        iter::successors(Some(self.data()), |data| data.parent()).any(|data| data.key() == key)
+    }
+
     #[inline]
     pub fn children(&self) -> SyntaxNodeChildren {
         SyntaxNodeChildren::new(self.clone())
     }
 
+    #[inline]
+    pub fn children_by_kind(&self, kind: SyntaxKind) -> impl Iterator<Item = SyntaxNode> {
+        iter::successors(self.first_child_by_kind(kind), move |node| {
+            node.next_sibling_by_kind(kind)
+        })
+    }
+
     #[inline]
     pub fn children_with_tokens(&self) -> SyntaxElementChildren {
         SyntaxElementChildren::new(self.clone())
@@ -643,6 +803,18 @@ impl SyntaxNode {
             })
         })
     }
+    pub fn first_child_by_kind(&self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        self.green_ref().children().raw.enumerate().find_map(|(index, child)| {
+            child.as_ref().into_node().filter(|green| green.kind() == kind).map(|green| {
+                SyntaxNode::new_child(
+                    green,
+                    self.clone(),
+                    index as u32,
+                    self.offset() + child.rel_offset(),
+                )
+            })
+        })
+    }
     pub fn last_child(&self) -> Option<SyntaxNode> {
         self.green_ref().children().raw.enumerate().rev().find_map(|(index, child)| {
             child.as_ref().into_node().map(|green| {
@@ -675,6 +847,43 @@ impl SyntaxNode {
     pub fn next_sibling(&self) -> Option<SyntaxNode> {
         self.data().next_sibling()
     }
+
+    /// Like `next_sibling`, but moves this cursor to the sibling in place if
+    /// nothing else refers to it, instead of allocating a new one. This is
+    /// only done in immutable trees.
+    pub(crate) fn into_next_sibling(self) -> Option<SyntaxNode> {
+        let data = self.data();
+        if data.mutable || data.rc.get() != 1 || data.parent().is_none() {
+            return self.next_sibling();
+        }
+        let mut siblings = data.green_siblings().enumerate();
+        siblings.nth(data.index() as usize);
+        let (index, rel_offset, green) = siblings.find_map(|(index, child)| match child {
+            GreenChild::Node { rel_offset, node } => {
+                Some((index as u32, *rel_offset, ptr::NonNull::from(&**node)))
+            }
+            GreenChild::Token { .. } => None,
+        })?;
+        let offset = data.parent().unwrap().offset() + rel_offset;
+        match &data.green {
+            Green::Node { ptr } => ptr.set(green),
+            Green::Token { .. } => unreachable!(),
+        }
+        data.index.set(index);
+        data.offset.set(offset);
+        Some(self)
+    }
+
+    /// Calls `f` for every node of this subtree in preorder, like iterating
+    /// over `preorder`. The cursor of a node is reused for its next sibling
+    /// unless `f` cloned it, so new cursors are only created for the first
+    /// child nodes of nodes.
+    pub fn visit(&self, f: impl FnMut(WalkEvent<&SyntaxNode>)) {
+        visit(self, f)
+    }
+    pub fn next_sibling_by_kind(&self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        self.data().next_sibling_by_kind(kind)
+    }
     pub fn prev_sibling(&self) -> Option<SyntaxNode> {
         self.data().prev_sibling()
     }
@@ -739,6 +948,24 @@ impl SyntaxNode {
         PreorderWithTokens::new(self.clone())
     }
 
+    pub fn contains_kind(&self, kind: SyntaxKind) -> bool {
+        if self.kind() == kind {
+            return true;
+        }
+        let mut stack = vec![self.green_ref().children()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(child) if child.kind() == kind => return true,
+                Some(NodeOrToken::Node(node)) => stack.push(node.children()),
+                Some(NodeOrToken::Token(_)) => (),
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        false
+    }
+
     pub fn token_at_offset(&self, offset: TextSize) -> TokenAtOffset<SyntaxToken> {
         // TODO: this could be faster if we first drill-down to node, and only
         // then switch to token search. We should also replace explicit
@@ -795,6 +1022,13 @@ impl SyntaxNode {
         }
     }
 
+    pub fn token_covering(&self, range: TextRange) -> Option<SyntaxToken> {
+        if !self.text_range().contains_range(range) {
+            return None;
+        }
+        self.covering_element(range).into_token()
+    }
+
     pub fn child_or_token_at_range(&self, range: TextRange) -> Option<SyntaxElement> {
         let rel_range = range - self.offset();
         self.green_ref().child_at_range(rel_range).map(|(index, rel_offset, green)| {
@@ -858,6 +1092,10 @@ impl SyntaxToken {
         parent.replace_with(new_parent)
     }
 
+    pub fn with_text(&self, text: &str) -> SyntaxNode {
+        SyntaxNode::new_root(self.replace_with(self.green().with_text(text)))
+    }
+
     #[inline]
     pub fn kind(&self) -> SyntaxKind {
         self.data().kind()
@@ -873,6 +1111,16 @@ impl SyntaxToken {
         self.data().index() as usize
     }
 
+    #[inline]
+    pub fn offset_in_parent(&self) -> TextSize {
+        self.data().offset_in_parent()
+    }
+
+    #[inline]
+    pub fn range_in_parent(&self) -> TextRange {
+        TextRange::at(self.offset_in_parent(), self.data().green().text_len())
+    }
+
     #[inline]
     pub fn text(&self) -> &str {
         match self.data().green().as_token() {
@@ -888,6 +1136,21 @@ impl SyntaxToken {
         }
     }
 
+    pub fn syntax_text(&self) -> SyntaxText {
+        match self.parent() {
+            Some(parent) => {
+                let range = self.text_range() - parent.text_range().start();
+                parent.text().slice(range)
+            }
+            // A detached token has no node to take the text from, so it is
+            // wrapped in a new root.
+            None => {
+                let green = GreenNode::new(self.kind(), iter::once(self.green().to_owned().into()));
+                SyntaxNode::new_root(green).text()
+            }
+        }
+    }
+
     #[inline]
     pub fn green(&self) -> &GreenTokenData {
         self.data().green().into_token().unwrap()
@@ -903,6 +1166,12 @@ impl SyntaxToken {
         std::iter::successors(self.parent(), SyntaxNode::parent)
     }
 
+    #[inline]
+    pub fn root(&self) -> Option<SyntaxNode> {
+        self.data().parent()?;
+        Some(self.data().root_node())
+    }
+
     pub fn next_sibling_or_token(&self) -> Option<SyntaxElement> {
         self.data().next_sibling_or_token()
     }
@@ -1277,4 +1546,176 @@ impl Iterator for PreorderWithTokens {
         next
     }
 }
+
+/// The nodes `visit` can walk: the cursor, and the typed nodes wrapping it.
+pub(crate) trait Visit: Sized {
+    fn first_child(&self) -> Option<Self>;
+    /// Moves the cursor to its next sibling node, in place if possible.
+    fn into_next_sibling(self) -> Option<Self>;
+}
+
+impl Visit for SyntaxNode {
+    fn first_child(&self) -> Option<SyntaxNode> {
+        self.first_child()
+    }
+    fn into_next_sibling(self) -> Option<SyntaxNode> {
+        self.into_next_sibling()
+    }
+}
+
+/// The loop of [`SyntaxNode::visit`], shared with the typed nodes.
+pub(crate) fn visit<N: Visit>(root: &N, mut f: impl FnMut(WalkEvent<&N>)) {
+    f(WalkEvent::Enter(root));
+    let mut stack: Vec<N> = Vec::new();
+    let mut next = root.first_child();
+    loop {
+        match next {
+            Some(node) => {
+                f(WalkEvent::Enter(&node));
+                next = node.first_child();
+                stack.push(node);
+            }
+            None => match stack.pop() {
+                Some(node) => {
+                    f(WalkEvent::Leave(&node));
+                    next = node.into_next_sibling();
+                }
+                None => break,
+            },
+        }
+    }
+    f(WalkEvent::Leave(root));
+}
 // endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::{GreenNodeBuilder, SyntaxKind};
+
+    use super::*;
+
+    /// Builds `ROOT[NODE["a"] "b" NODE["c"] NODE["d"]]`.
+    fn build_tree() -> SyntaxNode {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        for (i, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            if i == 1 {
+                builder.token(SyntaxKind(2), text);
+            } else {
+                builder.start_node(SyntaxKind(1));
+                builder.token(SyntaxKind(2), text);
+                builder.finish_node();
+            }
+        }
+        builder.finish_node();
+        SyntaxNode::new_root(builder.finish())
+    }
+
+    #[test]
+    fn test_prev_sibling() {
+        let root = build_tree();
+        let nodes: Vec<_> = root.children().collect();
+        assert_eq!(nodes.len(), 3);
+
+        assert_eq!(nodes[0].prev_sibling(), None);
+        assert_eq!(nodes[1].prev_sibling(), Some(nodes[0].clone()));
+        assert_eq!(nodes[2].prev_sibling(), Some(nodes[1].clone()));
+        assert_eq!(root.prev_sibling(), None);
+    }
+
+    #[test]
+    fn test_is_descendant_of() {
+        let root = build_tree();
+        let child = root.first_child().unwrap();
+        assert!(child.is_descendant_of(&root));
+        assert!(child.is_descendant_of(&child));
+        assert!(!root.is_descendant_of(&child));
+        assert!(!child.is_descendant_of(&child.next_sibling().unwrap()));
+
+        // Overlapping ranges, but a different tree.
+        let token = GreenToken::new(SyntaxKind(2), "abcd");
+        let inner = GreenNode::new(SyntaxKind(1), vec![token.into()]);
+        let other = SyntaxNode::new_root(GreenNode::new(SyntaxKind(0), vec![inner.into()]));
+        assert!(!other.first_child().unwrap().is_descendant_of(&root));
+        assert!(!child.is_descendant_of(&other));
+    }
+
+    #[test]
+    fn test_root() {
+        let root = build_tree().clone_for_update();
+        let child = root.first_child().unwrap();
+        assert_eq!(child.root(), root);
+        assert_eq!(root.root(), root);
+        child.detach();
+        assert_eq!(child.root(), child);
+
+        let token = root.first_token().unwrap();
+        assert_eq!(token.root(), Some(root.clone()));
+        token.detach();
+        assert_eq!(token.root(), None);
+    }
+
+    #[test]
+    fn test_visit() {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        for i in 0..3 {
+            builder.start_node(SyntaxKind(1));
+            builder.token(SyntaxKind(2), "a");
+            for _ in 0..i {
+                builder.start_node(SyntaxKind(3));
+                builder.token(SyntaxKind(2), "b");
+                builder.finish_node();
+            }
+            builder.finish_node();
+        }
+        builder.finish_node();
+        let green = builder.finish();
+
+        fn event(it: WalkEvent<&SyntaxNode>) -> (bool, SyntaxKind, TextRange) {
+            match it {
+                WalkEvent::Enter(it) => (true, it.kind(), it.text_range()),
+                WalkEvent::Leave(it) => (false, it.kind(), it.text_range()),
+            }
+        }
+        for root in [SyntaxNode::new_root(green.clone()), SyntaxNode::new_root_mut(green)] {
+            let expected: Vec<_> = root
+                .preorder()
+                .map(|it| match &it {
+                    WalkEvent::Enter(node) => event(WalkEvent::Enter(node)),
+                    WalkEvent::Leave(node) => event(WalkEvent::Leave(node)),
+                })
+                .collect();
+            let mut actual = Vec::new();
+            root.visit(|it| actual.push(event(it)));
+            assert_eq!(actual, expected);
+
+            // Nodes kept by `f` must not be moved to their siblings.
+            let mut kept = Vec::new();
+            root.visit(|it| {
+                if let WalkEvent::Enter(it) = it {
+                    kept.push(it.clone());
+                }
+            });
+            let expected: Vec<_> = root.descendants().collect();
+            assert_eq!(kept, expected);
+            for (kept, expected) in kept.iter().zip(&expected) {
+                assert_eq!(kept.text_range(), expected.text_range());
+                assert_eq!(kept.index(), expected.index());
+            }
+        }
+    }
+
+    #[test]
+    fn test_token_syntax_text() {
+        let root = build_tree().clone_for_update();
+        let b = root.first_child().unwrap().next_sibling_or_token().unwrap().into_token().unwrap();
+        assert_eq!(b.syntax_text(), "b");
+        assert_eq!(b.syntax_text().to_string(), b.text());
+
+        b.detach();
+        assert_eq!(b.parent(), None);
+        assert_eq!(b.syntax_text(), "b");
+        assert_eq!(b.syntax_text().to_buf().text_range(), b.text_range());
+    }
+}
@@ -3,15 +3,28 @@ mod token;
 mod element;
 mod builder;
 mod node_cache;
+mod sexpr;
+mod offset_index;
+mod edit_distance;
+mod hash_tree;
 
 use self::element::GreenElement;
 
-pub(crate) use self::{element::GreenElementRef, node::GreenChild};
+pub(crate) use self::{element::GreenElementRef, node::GreenChild, node_cache::node_hash};
 
 pub use self::{
-    builder::{Checkpoint, GreenNodeBuilder},
-    node::{Children, GreenNode, GreenNodeData},
-    node_cache::NodeCache,
+    builder::{
+        build_from_events, Checkpoint, Event, GreenBuilderOptions, GreenNodeBuilder, SpanMap,
+    },
+    edit_distance::tree_edit_distance,
+    hash_tree::HashTree,
+    node::{
+        from_token_stream, length_delta, merge_nodes, Children, GreenNode, GreenNodeData,
+        GreenNodeParts,
+    },
+    node_cache::{NodeCache, TokenInterner},
+    offset_index::OffsetIndex,
+    sexpr::ParseError,
     token::{GreenToken, GreenTokenData},
 };
 
@@ -22,6 +35,15 @@ pub struct SyntaxKind(pub u16);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{NodeOrToken, TextRange};
+
+    fn token(kind: u16, text: &str) -> GreenElement {
+        GreenToken::new(SyntaxKind(kind), text).into()
+    }
+
+    fn node(kind: u16, children: Vec<GreenElement>) -> GreenNode {
+        GreenNode::new(SyntaxKind(kind), children)
+    }
 
     #[test]
     fn assert_send_sync() {
@@ -39,4 +61,887 @@ mod tests {
         eprintln!("GreenToken         {}", size_of::<GreenToken>());
         eprintln!("GreenElement       {}", size_of::<GreenElement>());
     }
+
+    #[test]
+    fn test_finish_with_spans() {
+        let mut builder = GreenNodeBuilder::new();
+        builder.record_spans();
+        builder.start_node(SyntaxKind(0));
+        let checkpoint = builder.checkpoint();
+        builder.token_with_span(SyntaxKind(1), "a", 10..11);
+        builder.start_node_at(checkpoint, SyntaxKind(2));
+        builder.token(SyntaxKind(1), "+");
+        builder.start_node(SyntaxKind(3));
+        builder.token_with_span(SyntaxKind(1), "b", 20..23);
+        builder.finish_node();
+        builder.finish_node();
+        builder.start_node(SyntaxKind(4));
+        builder.finish_node();
+        builder.finish_node();
+        let (node, spans) = builder.finish_with_spans();
+
+        assert_eq!(node.to_string(), "a+b");
+        assert_eq!(spans.len(), 4);
+        assert_eq!(spans.get(0), Some(10..23));
+        assert_eq!(spans.get(1), Some(10..23));
+        assert_eq!(spans.get(2), Some(20..23));
+        assert_eq!(spans.get(3), Some(23..23));
+        assert_eq!(spans.get(4), None);
+    }
+
+    #[test]
+    fn test_preintern() {
+        let mut cache = NodeCache::default();
+        let kw = cache.preintern(SyntaxKind(1), "fn");
+
+        let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "fn");
+        builder.finish_node();
+        let node = builder.finish();
+
+        let token = node.children().next().unwrap().into_token().unwrap();
+        assert!(std::ptr::eq(token, &*kw));
+    }
+
+    #[test]
+    fn test_build_from_events() {
+        let node = build_from_events(vec![
+            Event::Open(SyntaxKind(0)),
+            Event::Token(SyntaxKind(1), "a"),
+            Event::Open(SyntaxKind(2)),
+            Event::Token(SyntaxKind(1), "b"),
+            Event::Close,
+            Event::Close,
+        ]);
+        assert_eq!(node.kind(), SyntaxKind(0));
+        assert_eq!(node.children().len(), 2);
+        assert_eq!(node.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_drop_empty_nodes() {
+        let options =
+            GreenBuilderOptions { drop_empty_nodes: true, ..GreenBuilderOptions::default() };
+        let build = |builder: GreenNodeBuilder<'_>| {
+            let mut builder = builder.with_options(options);
+            builder.start_node(SyntaxKind(0));
+            builder.start_node(SyntaxKind(1));
+            builder.start_node(SyntaxKind(2));
+            builder.finish_node();
+            builder.finish_node();
+            builder.token(SyntaxKind(3), "a");
+            builder.finish_node();
+            builder.finish()
+        };
+        let node = build(GreenNodeBuilder::new());
+        assert_eq!(node.children().len(), 1);
+        assert_eq!(node.children().next().unwrap().kind(), SyntaxKind(3));
+
+        let mut cache = NodeCache::default();
+        assert_eq!(build(GreenNodeBuilder::with_cache(&mut cache)), node);
+
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        builder.start_node(SyntaxKind(1));
+        builder.finish_node();
+        builder.finish_node();
+        assert_eq!(builder.finish().children().len(), 1);
+    }
+
+    #[test]
+    fn test_source_check() {
+        let mut cache = NodeCache::default();
+        let mut builder = GreenNodeBuilder::with_cache(&mut cache).with_source_check("fn f");
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "fn");
+        builder.token(SyntaxKind(2), " ");
+        builder.token(SyntaxKind(1), "f");
+        builder.finish_node();
+        assert_eq!(builder.finish().to_string(), "fn f");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "token text doesn't match the source at offset 2")]
+    fn test_source_check_mismatch() {
+        let mut builder = GreenNodeBuilder::new().with_source_check("fn f");
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "fn");
+        builder.token(SyntaxKind(1), "f");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "tokens don't cover the whole source")]
+    fn test_source_check_incomplete() {
+        let mut builder = GreenNodeBuilder::new().with_source_check("fn f");
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "fn");
+        builder.finish_node();
+        builder.finish();
+    }
+
+    #[test]
+    fn test_replace_children() {
+        let tree = node(0, vec![token(1, "a"), token(1, "b"), token(1, "c")]);
+        let res = tree.replace_children(vec![(2, token(1, "C")), (0, token(1, "x"))]);
+        assert_eq!(res.to_string(), "xbC");
+        assert_eq!(res.text_len(), 3.into());
+        let res = tree.replace_children(vec![(1, token(1, "x")), (1, token(1, "yy"))]);
+        assert_eq!(res.to_string(), "ayyc");
+        assert_eq!(tree.replace_children(vec![]), tree);
+    }
+
+    #[test]
+    #[should_panic(expected = "child index 3 is out of bounds")]
+    fn test_replace_children_out_of_bounds() {
+        let tree = node(0, vec![token(1, "a"), token(1, "b"), token(1, "c")]);
+        let _ = tree.replace_children(vec![(3, token(1, "d"))]);
+    }
+
+    #[test]
+    fn test_write_content() {
+        let content = |node: &GreenNode| {
+            let mut buf = Vec::new();
+            node.write_content(|bytes| buf.extend_from_slice(bytes));
+            buf
+        };
+        let tree = node(2, vec![token(1, "ab"), node(3, vec![]).into()]);
+        assert_eq!(
+            content(&tree),
+            [
+                &[0, 2, 0, 2, 0, 0, 0][..],
+                &[1, 1, 0, 2, 0, 0, 0, b'a', b'b'],
+                &[0, 3, 0, 0, 0, 0, 0]
+            ]
+            .concat()
+        );
+
+        let split = node(2, vec![token(1, "a"), token(1, "b"), node(3, vec![]).into()]);
+        assert_ne!(content(&tree), content(&split));
+    }
+
+    #[test]
+    fn test_eq_ignoring() {
+        const WS: SyntaxKind = SyntaxKind(9);
+        let ws = |text| token(WS.0, text);
+        let word = |text| token(1, text);
+        let inner = node(2, vec![word("b"), ws(" ")]);
+
+        let lhs = node(0, vec![word("a"), ws(" "), ws("\n"), inner.into(), ws(" ")]);
+        let inner = node(2, vec![ws(" "), word("b")]);
+        let rhs = node(0, vec![word("a"), inner.into()]);
+
+        assert!(lhs.eq_ignoring(&rhs, |kind| kind == WS));
+        assert!(rhs.eq_ignoring(&lhs, |kind| kind == WS));
+        assert!(!lhs.eq_ignoring(&rhs, |_| false));
+
+        let rhs = node(0, vec![word("a"), word("b")]);
+        assert!(!lhs.eq_ignoring(&rhs, |kind| kind == WS));
+    }
+
+    #[test]
+    fn test_sexpr_roundtrip() {
+        let text = "(0 1:\"fn\" 2:\" \\n\\t\" (3 4:\"\\\"a\\\\\" 4:\"\u{e9}\") (5))";
+        let node = GreenNode::from_sexpr(text, |kind| SyntaxKind(kind.parse().unwrap())).unwrap();
+        assert_eq!(node.to_string(), "fn \n\t\"a\\\u{e9}");
+        assert_eq!(node.to_sexpr(), text);
+
+        let err = GreenNode::from_sexpr("(0 1:\"a\"", |_| SyntaxKind(0)).unwrap_err();
+        assert_eq!(err.offset(), 8);
+    }
+
+    #[test]
+    fn test_merge_nodes() {
+        let a = node(2, vec![token(1, "a"), token(1, "b")]);
+        let b = node(3, vec![token(1, "c")]);
+        let merged = super::merge_nodes(SyntaxKind(4), &a, &b);
+        assert_eq!(merged.kind(), SyntaxKind(4));
+        assert_eq!(merged.to_string(), "abc");
+        assert_eq!(merged.text_len(), 3.into());
+        assert_eq!(merged.children().count(), 3);
+    }
+
+    #[test]
+    fn test_builder_default() {
+        let mut builder = GreenNodeBuilder::default();
+        builder.start_node(SyntaxKind(0));
+        builder.finish_node();
+        assert_eq!(builder.finish().kind(), SyntaxKind(0));
+    }
+
+    #[test]
+    fn test_on_token() {
+        let mut tokens = Vec::new();
+        let mut builder =
+            GreenNodeBuilder::default().on_token(|kind, range| tokens.push((kind, range)));
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "foo");
+        builder.token(SyntaxKind(2), " ");
+        builder.finish_node();
+        builder.finish();
+        assert_eq!(
+            tokens,
+            vec![
+                (SyntaxKind(1), TextRange::new(0.into(), 3.into())),
+                (SyntaxKind(2), TextRange::new(3.into(), 4.into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_tokens() {
+        let inner = node(10, vec![token(1, "a")]);
+        let root = node(11, vec![inner.clone().into(), node(10, vec![token(2, "b")]).into()]);
+        let mapped = root.map_tokens(|it| {
+            (it.kind() == SyntaxKind(2)).then(|| GreenToken::new(it.kind(), "bbb"))
+        });
+        assert_eq!(mapped.to_string(), "abbb");
+        assert_eq!(mapped.text_len(), 4.into());
+        let first = mapped.children().next().unwrap().into_node().unwrap();
+        assert!(std::ptr::eq(first, &*inner));
+    }
+
+    #[test]
+    fn test_walk_with_parents() {
+        let inner = node(2, vec![token(1, "a"), token(1, "b")]);
+        let root = node(3, vec![inner.into(), token(1, "c")]);
+        let parents: Vec<_> = root.walk_with_parents().map(|(_, parent)| parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(1), Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn test_find_subtrees() {
+        let pair = |kind, a, b| node(kind, vec![token(1, a), token(1, b)]);
+        let root =
+            node(100, vec![pair(10, "a", "b").into(), token(2, " "), pair(11, "a", "b").into()]);
+        let pattern = pair(10, "a", "b");
+        let ranges: Vec<_> = root.find_subtrees(&pattern, |lhs, rhs| lhs == rhs).collect();
+        assert_eq!(ranges, vec![TextRange::new(0.into(), 2.into())]);
+        let ranges: Vec<_> = root.find_subtrees(&pattern, |_, _| true).collect();
+        assert_eq!(
+            ranges,
+            vec![TextRange::new(0.into(), 2.into()), TextRange::new(3.into(), 5.into())]
+        );
+    }
+
+    #[test]
+    fn test_finish_hashed() {
+        let build = |text, checkpoint: bool| {
+            let mut builder = GreenNodeBuilder::new();
+            builder.record_hash();
+            builder.start_node(SyntaxKind(0));
+            let cp = builder.checkpoint();
+            builder.token(SyntaxKind(1), text);
+            if checkpoint {
+                builder.start_node_at(cp, SyntaxKind(2));
+            } else {
+                builder.start_node(SyntaxKind(2));
+            }
+            builder.finish_node();
+            builder.finish_node();
+            builder.finish_hashed()
+        };
+        let (plain, plain_hash) = build("a", false);
+        let (wrapped, wrapped_hash) = build("a", true);
+        assert_ne!(plain, wrapped);
+        assert_ne!(plain_hash, wrapped_hash);
+        assert_eq!(build("a", true).1, wrapped_hash);
+        assert_ne!(build("b", true).1, wrapped_hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "hashing is not enabled")]
+    fn test_finish_hashed_not_enabled() {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        builder.finish_node();
+        builder.finish_hashed();
+    }
+
+    #[test]
+    fn test_covering_element_multi() {
+        let inner = node(2, vec![token(1, "ab"), token(1, "cd")]);
+        let root = node(3, vec![token(1, "x"), inner.into()]);
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        assert_eq!(root.covering_element_multi(&[]).kind(), SyntaxKind(3));
+        assert_eq!(root.covering_element_multi(&[range(1, 2), range(4, 5)]).kind(), SyntaxKind(2));
+        let token = root.covering_element_multi(&[range(3, 4), range(4, 4)]).into_token().unwrap();
+        assert_eq!(token.text(), "cd");
+        assert_eq!(root.covering_element_multi(&[range(0, 1), range(3, 5)]).kind(), SyntaxKind(3));
+    }
+
+    #[test]
+    fn test_from_boxed() {
+        let children: Vec<GreenElement> = vec![token(1, "ab"), node(2, vec![token(1, "c")]).into()];
+        let expected = node(3, children.clone());
+        let node = GreenNode::from_boxed(SyntaxKind(3), children.into_boxed_slice());
+        assert_eq!(node, expected);
+        assert_eq!(node.text_len(), 3.into());
+    }
+
+    #[test]
+    fn test_offset_index() {
+        let inner = node(2, vec![token(1, "bc"), token(1, "")]);
+        let root = node(3, vec![token(1, "a"), inner.into(), token(1, "d")]);
+        let index = root.build_offset_index();
+        assert_eq!(index.len(), 3);
+        let text_at =
+            |offset: u32| index.token_at(offset.into()).map(|(_, it)| it.text().to_string());
+        assert_eq!(text_at(0).as_deref(), Some("a"));
+        assert_eq!(text_at(1).as_deref(), Some("bc"));
+        assert_eq!(text_at(2).as_deref(), Some("bc"));
+        assert_eq!(text_at(3).as_deref(), Some("d"));
+        assert_eq!(text_at(4), None);
+        assert_eq!(index.token_at(2.into()).unwrap().0, TextRange::new(1.into(), 3.into()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "empty token")]
+    fn test_forbid_empty_tokens() {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "");
+    }
+
+    #[test]
+    fn test_allow_empty_tokens() {
+        let options = GreenBuilderOptions { forbid_empty_tokens: false, ..Default::default() };
+        let mut builder = GreenNodeBuilder::new().with_options(options);
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "");
+        builder.finish_node();
+        assert_eq!(builder.finish().children().len(), 1);
+    }
+
+    #[test]
+    fn test_for_each_token_with_path() {
+        let inner = node(2, vec![token(1, "b")]);
+        let root = node(3, vec![token(1, "a"), inner.into()]);
+        let mut paths = Vec::new();
+        root.for_each_token_with_path(|path, token| {
+            paths.push((path.to_vec(), token.text().to_string()))
+        });
+        assert_eq!(
+            paths,
+            vec![
+                (vec![SyntaxKind(3)], "a".to_string()),
+                (vec![SyntaxKind(3), SyntaxKind(2)], "b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_with_text_edit() {
+        let inner = node(10, vec![token(1, "foo"), token(2, " ")]);
+        let root = node(11, vec![inner.into(), token(1, "bar")]);
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let reparse = |text: &str| {
+            let kind = if text.trim().is_empty() { 2 } else { 1 };
+            GreenToken::new(SyntaxKind(kind), text)
+        };
+        let edited = root.with_text_edit(range(1, 2), "xx", reparse).unwrap();
+        assert_eq!(edited.to_string(), "fxxo bar");
+        assert_eq!(edited.text_len(), 8.into());
+        assert_eq!(root.with_text_edit(range(2, 5), "", reparse), None);
+        assert_eq!(root.with_text_edit(range(3, 4), "x", reparse), None);
+    }
+
+    #[test]
+    fn test_length_delta() {
+        let a = node(0, vec![token(1, "a")]);
+        let abc = node(0, vec![token(1, "abc")]);
+        assert_eq!(super::length_delta(&abc, &a), -2);
+        assert_eq!(super::length_delta(&a, &abc), 2);
+    }
+
+    #[test]
+    fn test_green_node_parts() {
+        let mut parts = GreenNodeParts::new(SyntaxKind(0));
+        parts.push(token(1, "a"));
+        parts.push(token(1, "b"));
+        parts.push(token(1, "c"));
+        parts.remove_child(1);
+        *parts.nth_mut(1).unwrap() = token(1, "dd");
+        parts.set_kind(SyntaxKind(2));
+        let node = parts.finish();
+        assert_eq!(node.kind(), SyntaxKind(2));
+        assert_eq!(node.to_string(), "add");
+        assert_eq!(node.text_len(), 3.into());
+    }
+
+    #[test]
+    fn test_token_stream() {
+        let inner = node(10, vec![token(1, "b"), token(2, "c")]);
+        let root = node(11, vec![token(1, "a"), inner.into()]);
+        let mut stream = root.to_token_stream();
+        assert_eq!(stream.len(), 3);
+        stream[2].0 = SyntaxKind(3);
+        let flat = from_token_stream(SyntaxKind(12), stream);
+        assert_eq!(flat.to_string(), "abc");
+        let kinds: Vec<_> = flat.children().map(|it| it.kind()).collect();
+        assert_eq!(kinds, vec![SyntaxKind(1), SyntaxKind(1), SyntaxKind(3)]);
+    }
+
+    #[test]
+    fn test_tree_edit_distance() {
+        fn tok(cache: &mut NodeCache, text: &str) -> GreenToken {
+            cache.token(SyntaxKind(1), text).1
+        }
+        let mut cache = NodeCache::default();
+        let mut build = |texts: &[&str]| {
+            let children: Vec<GreenElement> =
+                texts.iter().map(|it| tok(&mut cache, it).into()).collect();
+            node(0, children)
+        };
+        let a = build(&["a", "b", "c"]);
+        let b = build(&["a", "x", "c", "d"]);
+        let unit = |x: Option<GreenElementRef<'_>>, y: Option<GreenElementRef<'_>>| match (x, y) {
+            (Some(NodeOrToken::Token(x)), Some(NodeOrToken::Token(y))) => (x != y) as u32,
+            (Some(x), Some(y)) => (x.kind() != y.kind()) as u32,
+            _ => 1,
+        };
+        assert_eq!(super::tree_edit_distance(&a, &a.clone(), unit), 0);
+        assert_eq!(super::tree_edit_distance(&a, &b, unit), 2);
+        assert_eq!(super::tree_edit_distance(&b, &a, unit), 2);
+    }
+
+    #[test]
+    fn test_tree_edit_distance_subtree_costs() {
+        use std::cell::Cell;
+
+        let deletes = Cell::new(0);
+        let cost = |x: Option<GreenElementRef<'_>>, y: Option<GreenElementRef<'_>>| {
+            if y.is_none() {
+                deletes.set(deletes.get() + 1);
+            }
+            match (x, y) {
+                (Some(x), Some(y)) => (x.kind() != y.kind()) as u32,
+                _ => u32::MAX / 2,
+            }
+        };
+        let big = node(1, (0..10).map(|_| token(2, "a")).collect());
+        let a = node(0, vec![big.into()]);
+        let b = node(0, vec![token(2, "x"), token(2, "y"), token(2, "z")]);
+        assert_eq!(super::tree_edit_distance(&a, &b, cost), u32::MAX);
+        // The cost of deleting the subtree is computed only once, although
+        // it is needed for every child of `b`.
+        assert_eq!(deletes.get(), 11);
+    }
+
+    #[test]
+    fn test_node_cache_max_children() {
+        fn build(cache: &mut NodeCache) -> GreenNode {
+            let mut builder = GreenNodeBuilder::with_cache(cache);
+            builder.start_node(SyntaxKind(0));
+            for _ in 0..2 {
+                builder.start_node(SyntaxKind(1));
+                for text in ["a", "b", "c", "d"] {
+                    builder.token(SyntaxKind(2), text);
+                }
+                builder.finish_node();
+            }
+            builder.finish_node();
+            builder.finish()
+        }
+        let node = build(&mut NodeCache::default());
+        assert_eq!(node.count_unique_subtrees(), 3);
+        let node = build(&mut NodeCache::with_max_children(4));
+        assert_eq!(node.count_unique_subtrees(), 2);
+    }
+
+    #[test]
+    fn test_set_gap() {
+        let ws = SyntaxKind(1);
+        let is_trivia = |kind| kind == ws;
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(2), "a");
+        builder.token(ws, "  ");
+        builder.start_node(SyntaxKind(3));
+        builder.token(ws, "\n");
+        builder.token(SyntaxKind(2), "b");
+        builder.finish_node();
+        builder.token(SyntaxKind(2), "c");
+        builder.finish_node();
+        let node = builder.finish();
+        let a = TextRange::at(0.into(), 1.into());
+        let b = TextRange::at(4.into(), 1.into());
+
+        let res = node.set_gap(a, GreenToken::new(ws, " "), is_trivia);
+        assert_eq!(res.to_string(), "a bc");
+        assert_eq!(res.children().len(), 4);
+        let res = node.set_gap(b, GreenToken::new(ws, " "), is_trivia);
+        assert_eq!(res.to_string(), "a  \nb c");
+        let res = node.set_gap(a, GreenToken::new(ws, ""), is_trivia);
+        assert_eq!(res.to_string(), "abc");
+        let res = node.set_gap(TextRange::empty(0.into()), GreenToken::new(ws, " "), is_trivia);
+        assert_eq!(res.to_string(), " a  \nbc");
+    }
+
+    #[test]
+    fn test_shape_hash() {
+        let ident = SyntaxKind(1);
+        let make = |name: &str| {
+            let mut builder = GreenNodeBuilder::new();
+            builder.start_node(SyntaxKind(0));
+            builder.token(ident, name);
+            builder.start_node(SyntaxKind(2));
+            builder.token(ident, "x");
+            builder.finish_node();
+            builder.finish_node();
+            builder.finish()
+        };
+        assert_eq!(make("foo").shape_hash(), make("bar").shape_hash());
+
+        let flat =
+            node(0, vec![GreenToken::new(ident, "foo").into(), GreenToken::new(ident, "x").into()]);
+        assert_ne!(make("foo").shape_hash(), flat.shape_hash());
+    }
+
+    #[test]
+    fn test_node_cache_warm_from() {
+        let build = |cache: &mut NodeCache| {
+            let mut builder = GreenNodeBuilder::with_cache(cache);
+            builder.start_node(SyntaxKind(0));
+            builder.start_node(SyntaxKind(1));
+            builder.token(SyntaxKind(2), "a");
+            builder.finish_node();
+            builder.finish_node();
+            builder.finish()
+        };
+        let old = build(&mut NodeCache::default());
+        let mut cache = NodeCache::default();
+        cache.warm_from(&old);
+        let new = build(&mut cache);
+        let child =
+            |node: &GreenNode| node.children().next().unwrap().into_node().unwrap() as *const _;
+        assert_eq!(child(&old), child(&new));
+    }
+
+    #[test]
+    fn test_child_pairs() {
+        let node = node(0, vec![token(1, "a"), token(2, "  "), token(1, "bc")]);
+        let pairs: Vec<_> = node
+            .child_pairs()
+            .map(|(lhs, rhs, offset)| (lhs.kind(), rhs.kind(), u32::from(offset)))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![(SyntaxKind(1), SyntaxKind(2), 1), (SyntaxKind(2), SyntaxKind(1), 3)]
+        );
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let tree = node(
+            0,
+            vec![token(1, "a"), node(0, vec![token(1, "b"), token(1, "c")]).into(), token(1, "d")],
+        );
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+
+        let res = tree.apply_edits(&[
+            (range(0, 0), node(0, vec![token(1, "<")])),
+            (range(1, 2), node(0, vec![token(1, "B")])),
+            (range(3, 4), node(0, vec![token(1, "D")])),
+        ]);
+        assert_eq!(res.to_string(), "<aBcD");
+        assert_eq!(res.children().nth(2).unwrap().into_node().unwrap().children().len(), 2);
+
+        let res = tree.apply_edits(&[
+            (range(1, 3), node(0, vec![token(1, "x")])),
+            (range(4, 4), node(0, vec![])),
+        ]);
+        assert_eq!(res.to_string(), "axd");
+        assert_eq!(res.children().len(), 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_apply_edits_overlapping() {
+        let node = node(0, vec![token(1, "ab")]);
+        let range = TextRange::new(0.into(), 2.into());
+        let _ = node.apply_edits(&[(range, node.clone()), (range, node.clone())]);
+    }
+
+    #[test]
+    fn test_is_empty_has_text() {
+        let empty = node(0, vec![]);
+        assert!(empty.is_empty() && !empty.has_text());
+        let with_empty_token = node(0, vec![token(1, "")]);
+        assert!(!with_empty_token.is_empty() && !with_empty_token.has_text());
+    }
+
+    #[test]
+    fn test_hash_tree() {
+        let shared = node(0, vec![token(1, "a")]);
+        let old = node(
+            0,
+            vec![shared.clone().into(), node(0, vec![token(1, "b")]).into(), shared.clone().into()],
+        );
+        let new = node(0, vec![shared.clone().into(), node(0, vec![token(1, "c")]).into()]);
+
+        let old_hashes = old.hash_tree();
+        assert_eq!(old_hashes.len(), 4);
+        assert_eq!(old_hashes.hash(1), old_hashes.hash(3));
+        assert_eq!(old_hashes.hash(0), crate::cursor::SyntaxNode::new_root(old).content_hash());
+
+        let new_hashes = new.hash_tree();
+        assert_eq!(new_hashes.changed_since(&old_hashes), vec![0, 2]);
+        assert_eq!(old_hashes.changed_since(&new_hashes), vec![0, 2, 3]);
+        assert_eq!(new_hashes.changed_since(&new_hashes), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_try_for_each_token() {
+        let tree =
+            node(0, vec![token(1, "a"), node(0, vec![token(1, "\tb"), token(1, "c")]).into()]);
+
+        let mut seen = Vec::new();
+        let res = tree.try_for_each_token(|token| {
+            seen.push(token.text().to_string());
+            if token.text().contains('\t') {
+                return Err(token.clone());
+            }
+            Ok(())
+        });
+        assert_eq!(res.unwrap_err().text(), "\tb");
+        assert_eq!(seen, vec!["a", "\tb"]);
+    }
+
+    #[test]
+    fn test_to_snapshot() {
+        let tree = node(0, vec![node(0, vec![token(1, "a\n")]).into(), token(2, " ")]);
+        let names = |kind: SyntaxKind| ["ROOT", "IDENT", "WS"][kind.0 as usize];
+        assert_eq!(tree.to_snapshot(names), "ROOT\n  ROOT\n    IDENT \"a\\n\"\n  WS \" \"\n");
+    }
+
+    #[test]
+    fn test_with_kind() {
+        let child: GreenElement = node(1, vec![]).into();
+        let node = node(0, vec![token(2, "a"), child]);
+        let res = node.with_kind(SyntaxKind(3));
+        assert_eq!(res.kind(), SyntaxKind(3));
+        assert_eq!(res.text_len(), node.text_len());
+        assert!(res.children().eq(node.children()));
+    }
+
+    #[test]
+    fn test_text_matches() {
+        let tree =
+            node(0, vec![token(1, "fn"), node(0, vec![token(1, " "), token(1, "é")]).into()]);
+        assert!(tree.text_matches("fn é"));
+        assert!(!tree.text_matches("fn e"));
+        assert!(!tree.text_matches("fn é "));
+        assert!(!tree.text_matches("fx é"));
+    }
+
+    #[test]
+    fn test_shared_token_interner() {
+        use std::sync::{Arc, Mutex};
+
+        let interner = Arc::new(Mutex::new(TokenInterner::default()));
+        let build = |cache: &mut NodeCache| {
+            let mut builder = GreenNodeBuilder::with_cache(cache);
+            builder.start_node(SyntaxKind(0));
+            builder.token(SyntaxKind(1), "ident");
+            builder.finish_node();
+            builder.finish()
+        };
+        let first_token = |node: &GreenNode| {
+            node.children().next().unwrap().into_token().unwrap() as *const GreenTokenData
+        };
+        let a = build(&mut NodeCache::with_token_interner(interner.clone()));
+        let b = build(&mut NodeCache::with_token_interner(interner.clone()));
+        assert_eq!(first_token(&a), first_token(&b));
+        assert_eq!(interner.lock().unwrap().len(), 1);
+
+        let mut bounded = TokenInterner::with_capacity(1);
+        let x = bounded.intern(GreenToken::new(SyntaxKind(1), "x"));
+        assert!(std::ptr::eq(&*bounded.intern(GreenToken::new(SyntaxKind(1), "x")), &*x));
+        let y = GreenToken::new(SyntaxKind(1), "y");
+        assert!(std::ptr::eq(&*bounded.intern(y.clone()), &*y));
+        assert_eq!(bounded.len(), 1);
+    }
+
+    #[test]
+    fn test_shared_token_interner_front_cache() {
+        use std::sync::{Arc, Mutex};
+
+        let interner = Arc::new(Mutex::new(TokenInterner::default()));
+        let mut cache = NodeCache::with_token_interner(interner.clone());
+        let ident = cache.preintern(SyntaxKind(1), "ident");
+
+        // Tokens this cache has seen are served without taking the lock.
+        let guard = interner.lock().unwrap();
+        let mut builder = GreenNodeBuilder::with_cache(&mut cache);
+        builder.start_node(SyntaxKind(0));
+        builder.token(SyntaxKind(1), "ident");
+        builder.finish_node();
+        let node = builder.finish();
+        drop(guard);
+        let token = node.children().next().unwrap().into_token().unwrap();
+        assert!(std::ptr::eq(token, &*ident));
+
+        // A panic while holding the lock doesn't break other caches.
+        let poison = interner.clone();
+        std::thread::spawn(move || {
+            let _guard = poison.lock().unwrap();
+            panic!("poison the interner");
+        })
+        .join()
+        .unwrap_err();
+        assert!(interner.is_poisoned());
+        let mut other = NodeCache::with_token_interner(interner.clone());
+        assert!(std::ptr::eq(&*other.preintern(SyntaxKind(1), "ident"), &*ident));
+        assert_eq!(other.intern_token(GreenToken::new(SyntaxKind(1), "new")).text(), "new");
+    }
+
+    #[test]
+    fn test_token_new_cow() {
+        use std::borrow::Cow;
+
+        let borrowed = GreenToken::new_cow(SyntaxKind(1), Cow::Borrowed("1"));
+        let owned = GreenToken::new_cow(SyntaxKind(1), Cow::Owned(1.to_string()));
+        assert_eq!(borrowed, owned);
+        assert_eq!(owned.text(), "1");
+    }
+
+    #[test]
+    fn test_remap_kinds() {
+        let tree = node(
+            0,
+            vec![token(1, "a"), node(2, vec![token(1, "b"), node(3, vec![]).into()]).into()],
+        );
+        let remapped = tree.remap_kinds(|kind| SyntaxKind(kind.0 + 10));
+        let expected = node(
+            10,
+            vec![token(11, "a"), node(12, vec![token(11, "b"), node(13, vec![]).into()]).into()],
+        );
+        assert_eq!(remapped, expected);
+        assert_eq!(remapped.to_string(), tree.to_string());
+        assert_eq!(tree.remap_kinds(|kind| kind), tree);
+    }
+
+    #[test]
+    fn test_kind_histogram() {
+        let inner = node(3, vec![token(1, "b"), node(3, vec![]).into(), token(2, " ")]);
+        let tree = node(0, vec![token(1, "a"), inner.clone().into(), token(1, "c")]);
+        let histogram = |pairs: &[(u16, usize)]| {
+            pairs.iter().map(|&(kind, count)| (SyntaxKind(kind), count)).collect()
+        };
+        assert_eq!(tree.kind_histogram(), histogram(&[(0, 1), (1, 3), (2, 1), (3, 2)]));
+        assert_eq!(inner.kind_histogram(), histogram(&[(1, 1), (2, 1), (3, 2)]));
+        assert_eq!(node(4, vec![]).kind_histogram(), histogram(&[(4, 1)]));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        // Words are sorted by their first letter, punctuation and nodes stay.
+        let sort_key = |it: GreenElementRef<'_>| match it {
+            NodeOrToken::Token(it) if it.kind() == SyntaxKind(1) => Some(it.text()[..1].into()),
+            _ => None,
+        };
+        let tree = node(
+            0,
+            vec![
+                token(1, "c"),
+                token(2, ","),
+                token(1, "b2"),
+                token(2, ","),
+                node(3, vec![token(1, "z"), token(2, " "), token(1, "x")]).into(),
+                token(1, "a"),
+                token(1, "b1"),
+            ],
+        );
+        let canonical = tree.canonicalize(sort_key);
+        let expected = node(
+            0,
+            vec![
+                token(1, "a"),
+                token(2, ","),
+                token(1, "b2"),
+                token(2, ","),
+                node(3, vec![token(1, "x"), token(2, " "), token(1, "z")]).into(),
+                token(1, "b1"),
+                token(1, "c"),
+            ],
+        );
+        assert_eq!(canonical, expected);
+        assert_eq!(canonical.to_string(), "a,b2,x zb1c");
+
+        let root = crate::cursor::SyntaxNode::new_root(canonical);
+        let mut offset = 0;
+        for token in root.descendants_with_tokens().filter_map(|it| it.into_token()) {
+            assert_eq!(u32::from(token.text_range().start()), offset, "{:?}", token);
+            offset += token.text().len() as u32;
+        }
+        assert_eq!(offset, u32::from(root.text_range().end()));
+    }
+
+    #[test]
+    fn test_write_text() {
+        use std::io;
+
+        /// Accepts at most `limit` bytes.
+        struct Limited {
+            buf: Vec<u8>,
+            limit: usize,
+        }
+
+        impl io::Write for Limited {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                if self.buf.len() + data.len() > self.limit {
+                    return Err(io::Error::other("full"));
+                }
+                self.buf.extend_from_slice(data);
+                Ok(data.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let tree = node(
+            0,
+            vec![token(1, "ab"), node(2, vec![node(3, vec![]).into(), token(1, "é")]).into()],
+        );
+        let mut buf = Vec::new();
+        tree.write_text(&mut buf).unwrap();
+        assert_eq!(buf, tree.to_string().into_bytes());
+
+        let mut w = Limited { buf: Vec::new(), limit: 3 };
+        let err = tree.write_text(&mut w).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(w.buf, b"ab");
+    }
+
+    #[test]
+    fn test_prefix() {
+        let tree = node(
+            0,
+            vec![
+                token(1, "ab"),
+                node(3, vec![token(1, "cd"), node(4, vec![]).into(), token(1, "ef")]).into(),
+                token(1, "gh"),
+            ],
+        );
+        let prefix = |len: u32| tree.prefix(len.into());
+
+        assert_eq!(prefix(0), node(0, vec![]));
+        assert_eq!(prefix(1), node(0, vec![]));
+        assert_eq!(prefix(2), node(0, vec![token(1, "ab")]));
+        assert_eq!(prefix(3), node(0, vec![token(1, "ab"), node(3, vec![]).into()]));
+        let cd = node(3, vec![token(1, "cd"), node(4, vec![]).into()]);
+        assert_eq!(prefix(4), node(0, vec![token(1, "ab"), cd.into()]));
+        assert_eq!(prefix(7).to_string(), "abcdef");
+        assert_eq!(prefix(8), tree);
+        assert_eq!(prefix(100), tree);
+
+        let tree = node(0, vec![token(1, "ab"), node(5, vec![]).into(), token(1, "c")]);
+        assert_eq!(tree.prefix(2.into()), node(0, vec![token(1, "ab"), node(5, vec![]).into()]));
+        assert_eq!(node(0, vec![]).prefix(0.into()), node(0, vec![]));
+    }
 }
@@ -1,7 +1,15 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Range,
+};
+
+use rustc_hash::FxHasher;
+
 use crate::{
     cow_mut::CowMut,
     green::{node_cache::NodeCache, GreenElement, GreenNode, SyntaxKind},
-    NodeOrToken,
+    NodeOrToken, TextRange, TextSize,
 };
 
 /// A checkpoint for maybe wrapping a node. See `GreenNodeBuilder::checkpoint` for details.
@@ -9,11 +17,201 @@ use crate::{
 pub struct Checkpoint(usize);
 
 /// A builder for a green tree.
-#[derive(Default, Debug)]
-pub struct GreenNodeBuilder<'cache> {
+///
+/// `F` is the type of the token observer, see `GreenNodeBuilder::on_token`.
+pub struct GreenNodeBuilder<'cache, F = fn(SyntaxKind, TextRange)> {
     cache: CowMut<'cache, NodeCache>,
     parents: Vec<(SyntaxKind, usize)>,
     children: Vec<(u64, GreenElement)>,
+    options: GreenBuilderOptions,
+    instrumentation: Option<Box<Instrumentation<F>>>,
+}
+
+impl Default for GreenNodeBuilder<'_> {
+    fn default() -> Self {
+        GreenNodeBuilder {
+            cache: CowMut::default(),
+            parents: Vec::new(),
+            children: Vec::new(),
+            options: GreenBuilderOptions::default(),
+            instrumentation: None,
+        }
+    }
+}
+
+impl<F> fmt::Debug for GreenNodeBuilder<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GreenNodeBuilder")
+            .field("cache", &self.cache)
+            .field("parents", &self.parents)
+            .field("children", &self.children)
+            .field("options", &self.options)
+            .field("instrumentation", &self.instrumentation)
+            .finish()
+    }
+}
+
+/// Options for a [`GreenNodeBuilder`], see `GreenNodeBuilder::with_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GreenBuilderOptions {
+    /// Don't emit nodes without children, except for the root node. Off by
+    /// default.
+    pub drop_empty_nodes: bool,
+    /// Assert in debug builds that tokens are not empty. Empty tokens are
+    /// usually a lexer bug, and they can't be found by
+    /// `SyntaxNode::covering_element` with a non-empty range. On by default,
+    /// grammars which use empty tokens on purpose need to turn it off.
+    pub forbid_empty_tokens: bool,
+}
+
+impl Default for GreenBuilderOptions {
+    fn default() -> Self {
+        GreenBuilderOptions { drop_empty_nodes: false, forbid_empty_tokens: true }
+    }
+}
+
+/// Source spans of the nodes of a green tree, see
+/// `GreenNodeBuilder::finish_with_spans`.
+///
+/// Spans are indexed by the preorder index of the node, the root node has
+/// index `0`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanMap {
+    spans: Vec<Range<usize>>,
+}
+
+impl SpanMap {
+    /// Returns the source span of the node with the given preorder index.
+    pub fn get(&self, index: usize) -> Option<Range<usize>> {
+        self.spans.get(index).cloned()
+    }
+
+    /// Returns the number of nodes in the map.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Returns `true` if the map contains no spans.
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+/// Optional work done for every token and node. It is kept behind a single
+/// `Option`, so that a builder which uses none of it only pays for one
+/// branch.
+struct Instrumentation<F> {
+    spans: Option<SpanRecorder>,
+    hasher: Option<FxHasher>,
+    #[cfg(debug_assertions)]
+    source_check: Option<SourceCheck>,
+    on_token: Option<(TextSize, F)>,
+}
+
+impl<F> Default for Instrumentation<F> {
+    fn default() -> Self {
+        Instrumentation {
+            spans: None,
+            hasher: None,
+            #[cfg(debug_assertions)]
+            source_check: None,
+            on_token: None,
+        }
+    }
+}
+
+impl<F> fmt::Debug for Instrumentation<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("Instrumentation");
+        f.field("spans", &self.spans);
+        f.field("hashed", &self.hasher.is_some());
+        #[cfg(debug_assertions)]
+        f.field("source_check", &self.source_check);
+        f.finish()
+    }
+}
+
+impl<F: FnMut(SyntaxKind, TextRange)> Instrumentation<F> {
+    fn token(&mut self, kind: SyntaxKind, text: &str, span: Option<Range<usize>>, hash: u64) {
+        if let Some(spans) = &mut self.spans {
+            let span = span.unwrap_or(spans.offset..spans.offset + text.len());
+            spans.token(span);
+        }
+        if let Some(hasher) = &mut self.hasher {
+            hash.hash(hasher);
+        }
+        #[cfg(debug_assertions)]
+        if let Some(source_check) = &mut self.source_check {
+            source_check.token(text);
+        }
+        if let Some((offset, on_token)) = &mut self.on_token {
+            let range = TextRange::at(*offset, TextSize::of(text));
+            *offset = range.end();
+            on_token(kind, range);
+        }
+    }
+
+    fn node(&mut self, kind: SyntaxKind, first_child: usize, n_children: usize) {
+        if let Some(spans) = &mut self.spans {
+            spans.node(first_child);
+        }
+        // Kinds and numbers of children of the nodes in postorder, together
+        // with the tokens, identify the tree.
+        if let Some(hasher) = &mut self.hasher {
+            kind.hash(hasher);
+            n_children.hash(hasher);
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct SpanRecorder {
+    offset: usize,
+    // Parallel to `GreenNodeBuilder::children`.
+    children: Vec<Range<usize>>,
+    // Spans of finished nodes, in postorder.
+    nodes: Vec<Range<usize>>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Debug)]
+struct SourceCheck {
+    src: String,
+    offset: usize,
+}
+
+#[cfg(debug_assertions)]
+impl SourceCheck {
+    fn token(&mut self, text: &str) {
+        let end = self.offset + text.len();
+        let expected = self.src.as_bytes().get(self.offset..end.min(self.src.len())).unwrap_or(&[]);
+        if expected != text.as_bytes() {
+            panic!(
+                "token text doesn't match the source at offset {}: expected {:?}, actual {:?}",
+                self.offset,
+                String::from_utf8_lossy(expected),
+                text,
+            );
+        }
+        self.offset = end;
+    }
+}
+
+impl SpanRecorder {
+    fn token(&mut self, span: Range<usize>) {
+        self.offset = span.end;
+        self.children.push(span);
+    }
+
+    fn node(&mut self, first_child: usize) {
+        let span = match (self.children.get(first_child), self.children.last()) {
+            (Some(first), Some(last)) => first.start..last.end,
+            _ => self.offset..self.offset,
+        };
+        self.children.truncate(first_child);
+        self.children.push(span.clone());
+        self.nodes.push(span);
+    }
 }
 
 impl GreenNodeBuilder<'_> {
@@ -29,13 +227,114 @@ impl GreenNodeBuilder<'_> {
             cache: CowMut::Borrowed(cache),
             parents: Vec::new(),
             children: Vec::new(),
+            options: GreenBuilderOptions::default(),
+            instrumentation: None,
+        }
+    }
+}
+
+impl<'cache, F: FnMut(SyntaxKind, TextRange)> GreenNodeBuilder<'cache, F> {
+    /// Calls `on_token` with the kind and the range of every token as it is
+    /// added, which allows to collect e.g. highlighting information without
+    /// walking the tree afterwards. Must be called before any tokens are
+    /// added.
+    pub fn on_token<G>(self, on_token: G) -> GreenNodeBuilder<'cache, G>
+    where
+        G: FnMut(SyntaxKind, TextRange),
+    {
+        assert!(self.children.is_empty(), "on_token called after tokens were added");
+        let Instrumentation {
+            spans,
+            hasher,
+            #[cfg(debug_assertions)]
+            source_check,
+            on_token: _,
+        } = self.instrumentation.map_or_else(Instrumentation::default, |it| *it);
+        GreenNodeBuilder {
+            cache: self.cache,
+            parents: self.parents,
+            children: self.children,
+            options: self.options,
+            instrumentation: Some(Box::new(Instrumentation {
+                spans,
+                hasher,
+                #[cfg(debug_assertions)]
+                source_check,
+                on_token: Some((0.into(), on_token)),
+            })),
         }
     }
 
+    /// Sets the options of this builder.
+    pub fn with_options(mut self, options: GreenBuilderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Makes the builder check that the text of the tokens matches `src`,
+    /// panicking on the first token that doesn't. Meant for debugging lexers
+    /// which lose or duplicate parts of the input. The check is only done in
+    /// debug builds, in release builds this does nothing. Must be called
+    /// before any tokens are added.
+    pub fn with_source_check(self, src: &str) -> Self {
+        assert!(self.children.is_empty(), "with_source_check called after tokens were added");
+        #[cfg(debug_assertions)]
+        {
+            let mut builder = self;
+            builder.instrumentation().source_check =
+                Some(SourceCheck { src: src.to_string(), offset: 0 });
+            builder
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            let _ = src;
+            self
+        }
+    }
+
+    /// Starts recording source spans of the nodes, which can be retrieved
+    /// with `finish_with_spans`. Must be called before any tokens are added.
+    pub fn record_spans(&mut self) {
+        assert!(self.children.is_empty(), "record_spans called after tokens were added");
+        self.instrumentation().spans = Some(SpanRecorder::default());
+    }
+
+    /// Starts computing a hash of the tree, which can be retrieved with
+    /// `finish_hashed`. Must be called before any tokens are added.
+    pub fn record_hash(&mut self) {
+        assert!(self.children.is_empty(), "record_hash called after tokens were added");
+        self.instrumentation().hasher = Some(FxHasher::default());
+    }
+
+    fn instrumentation(&mut self) -> &mut Instrumentation<F> {
+        self.instrumentation.get_or_insert_with(Box::default)
+    }
+
     /// Adds new token to the current branch.
     #[inline]
     pub fn token(&mut self, kind: SyntaxKind, text: &str) {
+        self.push_token(kind, text, None);
+    }
+
+    /// Adds new token to the current branch, remembering that it originates
+    /// from `span` in the source. Subsequent tokens added via `token` are
+    /// assumed to follow `span` directly.
+    #[inline]
+    pub fn token_with_span(&mut self, kind: SyntaxKind, text: &str, span: Range<usize>) {
+        self.push_token(kind, text, Some(span));
+    }
+
+    #[inline]
+    fn push_token(&mut self, kind: SyntaxKind, text: &str, span: Option<Range<usize>>) {
+        debug_assert!(
+            !(self.options.forbid_empty_tokens && text.is_empty()),
+            "empty token of kind {:?}",
+            kind
+        );
         let (hash, token) = self.cache.token(kind, text);
+        if let Some(instrumentation) = &mut self.instrumentation {
+            instrumentation.token(kind, text, span, hash);
+        }
         self.children.push((hash, token.into()));
     }
 
@@ -51,6 +350,15 @@ impl GreenNodeBuilder<'_> {
     #[inline]
     pub fn finish_node(&mut self) {
         let (kind, first_child) = self.parents.pop().unwrap();
+        if self.options.drop_empty_nodes
+            && first_child == self.children.len()
+            && !self.parents.is_empty()
+        {
+            return;
+        }
+        if let Some(instrumentation) = &mut self.instrumentation {
+            instrumentation.node(kind, first_child, self.children.len() - first_child);
+        }
         let (hash, node) = self.cache.node(kind, &mut self.children, first_child);
         self.children.push((hash, node.into()));
     }
@@ -111,9 +419,90 @@ impl GreenNodeBuilder<'_> {
     #[inline]
     pub fn finish(mut self) -> GreenNode {
         assert_eq!(self.children.len(), 1);
+        #[cfg(debug_assertions)]
+        if let Some(source_check) =
+            self.instrumentation.as_ref().and_then(|it| it.source_check.as_ref())
+        {
+            assert_eq!(
+                source_check.offset,
+                source_check.src.len(),
+                "tokens don't cover the whole source"
+            );
+        }
         match self.children.pop().unwrap().1 {
             NodeOrToken::Node(node) => node,
             NodeOrToken::Token(_) => panic!(),
         }
     }
+
+    /// Like `finish`, but also returns a hash of the tree, which is computed
+    /// while the tree is built. Equal trees have equal hashes, so comparing
+    /// the hashes is a cheap way to detect that a reparse didn't change
+    /// anything. The hash is different from `SyntaxNode::content_hash`.
+    /// Requires `record_hash` to be called before building the tree.
+    pub fn finish_hashed(mut self) -> (GreenNode, u64) {
+        let hasher = self
+            .instrumentation
+            .as_mut()
+            .and_then(|it| it.hasher.take())
+            .expect("hashing is not enabled");
+        (self.finish(), hasher.finish())
+    }
+
+    /// Like `finish`, but also returns the source spans recorded for each
+    /// node. Requires `record_spans` to be called before building the tree.
+    pub fn finish_with_spans(mut self) -> (GreenNode, SpanMap) {
+        let recorder = self
+            .instrumentation
+            .as_mut()
+            .and_then(|it| it.spans.take())
+            .expect("span recording is not enabled");
+        let node = self.finish();
+
+        // Nodes are finished in postorder, but the map is indexed in preorder.
+        let mut spans = vec![0..0; recorder.nodes.len()];
+        let mut preorder = 0;
+        let mut postorder = 0;
+        let mut stack = vec![(preorder, node.children())];
+        preorder += 1;
+        while let Some((index, children)) = stack.last_mut() {
+            match children.find_map(NodeOrToken::into_node) {
+                Some(child) => {
+                    stack.push((preorder, child.children()));
+                    preorder += 1;
+                }
+                None => {
+                    spans[*index] = recorder.nodes[postorder].clone();
+                    postorder += 1;
+                    stack.pop();
+                }
+            }
+        }
+        (node, SpanMap { spans })
+    }
+}
+
+/// A flat tree-building event, see [`build_from_events`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Start a new node of the given kind.
+    Open(SyntaxKind),
+    /// Finish the current node.
+    Close,
+    /// Add a token to the current node.
+    Token(SyntaxKind, &'a str),
+}
+
+/// Builds a tree from a flat stream of events. `Open` and `Close` events must
+/// be balanced and the stream must describe exactly one root node.
+pub fn build_from_events<'a>(events: impl IntoIterator<Item = Event<'a>>) -> GreenNode {
+    let mut builder = GreenNodeBuilder::new();
+    for event in events {
+        match event {
+            Event::Open(kind) => builder.start_node(kind),
+            Event::Close => builder.finish_node(),
+            Event::Token(kind, text) => builder.token(kind, text),
+        }
+    }
+    builder.finish()
 }
@@ -0,0 +1,115 @@
+//! Edit distance between green trees.
+
+use std::ptr;
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    green::{GreenElementRef, GreenNode, GreenNodeData},
+    NodeOrToken,
+};
+
+/// Computes the edit distance between two trees.
+///
+/// `cost(Some(x), None)` is the cost of deleting `x`, `cost(None, Some(y))`
+/// the cost of inserting `y`, and `cost(Some(x), Some(y))` the cost of
+/// replacing `x` with `y`, which should be `0` for equal elements. The
+/// costs are added up with saturating arithmetic.
+///
+/// This is the top-down (Selkow) variant of tree edit distance: a node is
+/// only matched with a node at the same depth, and deleting or inserting a
+/// node also deletes or inserts its whole subtree. The children of matched
+/// nodes are aligned like in the string edit distance. Subtrees which are
+/// shared between the trees, that is, the same `Arc`, as is common for
+/// trees built with the same `NodeCache`, are matched at zero cost without
+/// looking inside them.
+///
+/// The complexity is `O(n * m)` for trees of sizes `n` and `m` in the worst
+/// case, and proportional to the size of the changed parts if most subtrees
+/// are shared.
+pub fn tree_edit_distance(
+    a: &GreenNode,
+    b: &GreenNode,
+    cost: impl Fn(Option<GreenElementRef<'_>>, Option<GreenElementRef<'_>>) -> u32,
+) -> u32 {
+    let mut ctx = Ctx { cost: &cost, delete: FxHashMap::default(), insert: FxHashMap::default() };
+    ctx.distance(NodeOrToken::Node(a), NodeOrToken::Node(b))
+}
+
+type Cost<'c> = &'c dyn Fn(Option<GreenElementRef<'_>>, Option<GreenElementRef<'_>>) -> u32;
+
+struct Ctx<'c> {
+    cost: Cost<'c>,
+    /// Costs of deleting or inserting whole subtrees, computed once per node.
+    delete: FxHashMap<*const GreenNodeData, u32>,
+    insert: FxHashMap<*const GreenNodeData, u32>,
+}
+
+impl Ctx<'_> {
+    fn distance(&mut self, a: GreenElementRef<'_>, b: GreenElementRef<'_>) -> u32 {
+        match (a, b) {
+            (NodeOrToken::Node(x), NodeOrToken::Node(y)) => {
+                if ptr::eq(x, y) {
+                    return 0;
+                }
+                (self.cost)(Some(a), Some(b)).saturating_add(self.children_distance(x, y))
+            }
+            (NodeOrToken::Token(x), NodeOrToken::Token(y)) => {
+                if ptr::eq(x, y) {
+                    return 0;
+                }
+                (self.cost)(Some(a), Some(b))
+            }
+            _ => self.subtree_cost(a, true).saturating_add(self.subtree_cost(b, false)),
+        }
+    }
+
+    fn children_distance(&mut self, a: &GreenNodeData, b: &GreenNodeData) -> u32 {
+        let a: Vec<_> = a.children().collect();
+        let b: Vec<_> = b.children().collect();
+        let delete: Vec<_> = a.iter().map(|&it| self.subtree_cost(it, true)).collect();
+        let insert: Vec<_> = b.iter().map(|&it| self.subtree_cost(it, false)).collect();
+
+        // `prev[j]` is the distance between the first `i - 1` children of `a`
+        // and the first `j` children of `b`.
+        let mut prev: Vec<u32> = Vec::with_capacity(b.len() + 1);
+        prev.push(0);
+        for j in 0..b.len() {
+            prev.push(prev[j].saturating_add(insert[j]));
+        }
+        let mut curr = vec![0; b.len() + 1];
+        for i in 0..a.len() {
+            curr[0] = prev[0].saturating_add(delete[i]);
+            for j in 0..b.len() {
+                curr[j + 1] = prev[j]
+                    .saturating_add(self.distance(a[i], b[j]))
+                    .min(prev[j + 1].saturating_add(delete[i]))
+                    .min(curr[j].saturating_add(insert[j]));
+            }
+            std::mem::swap(&mut prev, &mut curr);
+        }
+        prev[b.len()]
+    }
+
+    /// Returns the cost of deleting or inserting the whole subtree of
+    /// `element`.
+    fn subtree_cost(&mut self, element: GreenElementRef<'_>, delete: bool) -> u32 {
+        let memo = if delete { &self.delete } else { &self.insert };
+        if let NodeOrToken::Node(node) = element {
+            if let Some(&res) = memo.get(&(node as *const _)) {
+                return res;
+            }
+        }
+        let cost = self.cost;
+        let own = if delete { cost(Some(element), None) } else { cost(None, Some(element)) };
+        let node = match element {
+            NodeOrToken::Node(node) => node,
+            NodeOrToken::Token(_) => return own,
+        };
+        let res =
+            node.children().fold(own, |acc, it| acc.saturating_add(self.subtree_cost(it, delete)));
+        let memo = if delete { &mut self.delete } else { &mut self.insert };
+        memo.insert(node as *const _, res);
+        res
+    }
+}
@@ -0,0 +1,124 @@
+//! Per-node fingerprints of a green tree, for finding changed subtrees.
+
+use std::hash::{Hash, Hasher};
+
+use rustc_hash::{FxHashMap, FxHasher};
+
+use crate::{
+    green::{node_cache::token_hash, GreenNodeData},
+    NodeOrToken,
+};
+
+/// Fingerprints of all nodes of a tree in preorder, see
+/// [`GreenNodeData::hash_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashTree {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    hash: u64,
+    /// Number of nodes in the subtree, including the node itself.
+    size: usize,
+    /// Number of child nodes.
+    child_nodes: usize,
+}
+
+impl HashTree {
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no nodes, which is never the case for a
+    /// `HashTree` of a tree.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the fingerprint of the subtree of the node with the given
+    /// preorder index. It is the same as `SyntaxNode::content_hash`.
+    pub fn hash(&self, index: usize) -> u64 {
+        self.entries[index].hash
+    }
+
+    /// Returns the preorder indices of the nodes of this tree which changed
+    /// compared to `old`, in order.
+    ///
+    /// The root corresponds to the root, and the `n`-th child node of a node
+    /// to the `n`-th child node of the corresponding node. A node changed if
+    /// its fingerprint differs from the one of its counterpart, or if it has
+    /// none. Subtrees with equal fingerprints are skipped without looking at
+    /// their descendants.
+    pub fn changed_since(&self, old: &HashTree) -> Vec<usize> {
+        let mut res = Vec::new();
+        self.diff(0, old, Some(0), &mut res);
+        res
+    }
+
+    fn diff(&self, index: usize, old: &HashTree, old_index: Option<usize>, res: &mut Vec<usize>) {
+        let entry = self.entries[index];
+        let old_entry = old_index.map(|it| (it, old.entries[it]));
+        if let Some((_, old_entry)) = old_entry {
+            if old_entry.hash == entry.hash {
+                return;
+            }
+        }
+        res.push(index);
+
+        let mut old_child = old_entry.map(|(it, _)| it + 1);
+        let old_end = old_entry.map_or(0, |(it, entry)| it + entry.size);
+        let mut child = index + 1;
+        for _ in 0..entry.child_nodes {
+            let old_index = old_child.filter(|&it| it < old_end);
+            self.diff(child, old, old_index, res);
+            child += self.entries[child].size;
+            old_child = old_index.map(|it| it + old.entries[it].size);
+        }
+    }
+}
+
+impl GreenNodeData {
+    /// Computes the fingerprints of all nodes of this tree in one pass.
+    /// Subtrees which are shared, that is, the same `Arc`, are only hashed
+    /// once.
+    pub fn hash_tree(&self) -> HashTree {
+        let mut entries = Vec::new();
+        let mut seen = FxHashMap::default();
+        build(self, &mut entries, &mut seen);
+        HashTree { entries }
+    }
+}
+
+fn build(
+    node: &GreenNodeData,
+    entries: &mut Vec<Entry>,
+    seen: &mut FxHashMap<*const GreenNodeData, usize>,
+) -> u64 {
+    let index = entries.len();
+    if let Some(&first) = seen.get(&(node as *const _)) {
+        let size = entries[first].size;
+        entries.extend_from_within(first..first + size);
+        return entries[index].hash;
+    }
+    seen.insert(node as *const _, index);
+
+    entries.push(Entry { hash: 0, size: 0, child_nodes: 0 });
+    let mut h = FxHasher::default();
+    node.kind().hash(&mut h);
+    let mut child_nodes = 0;
+    for child in node.children() {
+        match child {
+            NodeOrToken::Node(it) => {
+                child_nodes += 1;
+                build(it, entries, seen)
+            }
+            NodeOrToken::Token(it) => token_hash(it),
+        }
+        .hash(&mut h)
+    }
+    let hash = h.finish();
+    entries[index] = Entry { hash, size: entries.len() - index, child_nodes };
+    hash
+}
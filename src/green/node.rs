@@ -1,18 +1,23 @@
 use std::{
     borrow::{Borrow, Cow},
+    collections::{HashMap, HashSet},
     fmt,
+    hash::{Hash, Hasher},
+    io,
     iter::{self, FusedIterator},
     mem::{self, ManuallyDrop},
     ops, ptr, slice,
 };
 
 use countme::Count;
+use rustc_hash::FxHasher;
 
 use crate::{
     arc::{Arc, HeaderSlice, ThinArc},
-    green::{GreenElement, GreenElementRef, SyntaxKind},
+    ast::SyntaxNodePtr,
+    green::{GreenElement, GreenElementRef, GreenTokenData, SyntaxKind},
     utility_types::static_assert,
-    GreenToken, NodeOrToken, TextRange, TextSize,
+    GreenToken, Language, NodeOrToken, TextRange, TextSize,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -116,7 +121,7 @@ impl GreenNodeData {
     }
 
     #[inline]
-    fn slice(&self) -> &[GreenChild] {
+    pub(super) fn slice(&self) -> &[GreenChild] {
         self.data.slice()
     }
 
@@ -132,12 +137,269 @@ impl GreenNodeData {
         self.header().text_len
     }
 
+    /// Returns `true` if this node has no children. A node whose children are
+    /// all empty tokens is not empty, but has no text.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slice().is_empty()
+    }
+
+    /// Returns `true` if the text of this node is not empty.
+    #[inline]
+    pub fn has_text(&self) -> bool {
+        self.text_len() > 0.into()
+    }
+
     /// Children of this node.
     #[inline]
     pub fn children(&self) -> Children<'_> {
         Children { raw: self.slice().iter() }
     }
 
+    /// Iterates over all pairs of adjacent children, together with the
+    /// offset of the boundary between them relative to the start of this
+    /// node. Yields nothing if there are fewer than two children.
+    pub fn child_pairs(
+        &self,
+    ) -> impl Iterator<Item = (GreenElementRef<'_>, GreenElementRef<'_>, TextSize)> {
+        self.slice()
+            .windows(2)
+            .map(|pair| (pair[0].as_ref(), pair[1].as_ref(), pair[1].rel_offset()))
+    }
+
+    /// Returns the texts of all tokens in this tree together with their
+    /// offsets relative to the start of this node.
+    pub fn text_chunks(&self) -> impl Iterator<Item = (TextSize, &str)> {
+        let mut stack = vec![(TextSize::from(0), self.slice().iter())];
+        iter::from_fn(move || loop {
+            let (offset, children) = stack.last_mut()?;
+            match children.next() {
+                Some(child) => {
+                    let offset = *offset + child.rel_offset();
+                    match child.as_ref() {
+                        NodeOrToken::Node(node) => stack.push((offset, node.slice().iter())),
+                        NodeOrToken::Token(token) => return Some((offset, token.text())),
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        })
+    }
+
+    /// Iterates over all elements of the tree in preorder, starting with this
+    /// node. Each element is paired with the preorder index of its parent, or
+    /// `None` for this node, which allows to build a parent table in a single
+    /// pass.
+    pub fn walk_with_parents(&self) -> impl Iterator<Item = (GreenElementRef<'_>, Option<usize>)> {
+        let mut stack = vec![(0, self.children())];
+        let mut index = 1;
+        iter::once((NodeOrToken::Node(self), None)).chain(iter::from_fn(move || loop {
+            let (parent, children) = stack.last_mut()?;
+            match children.next() {
+                Some(child) => {
+                    let parent = *parent;
+                    if let NodeOrToken::Node(node) = child {
+                        stack.push((index, node.children()));
+                    }
+                    index += 1;
+                    return Some((child, Some(parent)));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }))
+    }
+
+    /// Calls `f` for every token of this tree, in order, together with the
+    /// kinds of the nodes containing it, from this node down to the token's
+    /// parent.
+    pub fn for_each_token_with_path(&self, mut f: impl FnMut(&[SyntaxKind], &GreenTokenData)) {
+        let mut path = vec![self.kind()];
+        let mut stack = vec![self.children()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(NodeOrToken::Node(node)) => {
+                    path.push(node.kind());
+                    stack.push(node.children());
+                }
+                Some(NodeOrToken::Token(token)) => f(&path, token),
+                None => {
+                    path.pop();
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    /// Calls `f` for every token of this tree, in order, stopping at the
+    /// first error, which is returned.
+    pub fn try_for_each_token<E>(
+        &self,
+        mut f: impl FnMut(&GreenToken) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut stack = vec![self.slice().iter()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(GreenChild::Node { node, .. }) => stack.push(node.slice().iter()),
+                Some(GreenChild::Token { token, .. }) => f(token)?,
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of distinct nodes in this tree, including itself,
+    /// counting nodes which are shared between several places once. Compare
+    /// with the number of nodes in `walk_with_parents` to see how much a
+    /// [`NodeCache`](crate::NodeCache) deduplicated.
+    pub fn count_unique_subtrees(&self) -> usize {
+        let mut seen = HashSet::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if seen.insert(node as *const GreenNodeData) {
+                stack.extend(node.children().filter_map(NodeOrToken::into_node));
+            }
+        }
+        seen.len()
+    }
+
+    /// Returns a hash of the shape of this tree: the kinds of all nodes and
+    /// tokens and how they are nested, but not the text of the tokens. Two
+    /// functions which differ only in names and literals have the same
+    /// shape hash.
+    ///
+    /// The other hashes of a tree include more information:
+    /// `SyntaxNode::content_hash` also includes the text of every token, and
+    /// so is equal exactly for trees which are `content_eq`, modulo
+    /// collisions. The hash returned by `GreenNodeBuilder::finish_hashed` is
+    /// computed while building and includes the same information as
+    /// `content_hash`, but with a different function.
+    pub fn shape_hash(&self) -> u64 {
+        let mut h = FxHasher::default();
+        self.shape_hash_into(&mut h);
+        h.finish()
+    }
+
+    fn shape_hash_into(&self, h: &mut FxHasher) {
+        self.kind().hash(h);
+        self.children().len().hash(h);
+        for child in self.children() {
+            match child {
+                NodeOrToken::Node(it) => it.shape_hash_into(h),
+                // Childless nodes and tokens are distinguished by the count.
+                NodeOrToken::Token(it) => {
+                    it.kind().hash(h);
+                    usize::MAX.hash(h);
+                }
+            }
+        }
+    }
+
+    /// Feeds a canonical serialization of this tree to `sink`, chunk by
+    /// chunk. It is meant as the input of a cryptographic hash like blake3,
+    /// to get a content-addressed id of a subtree which is stable across
+    /// processes:
+    ///
+    /// ```ignore
+    /// let mut hasher = blake3::Hasher::new();
+    /// node.write_content(|bytes| {
+    ///     hasher.update(bytes);
+    /// });
+    /// let content_id: [u8; 32] = *hasher.finalize().as_bytes();
+    /// ```
+    ///
+    /// The serialization of a node is the byte `0`, the kind as a
+    /// little-endian `u16`, the number of children as a little-endian `u32`,
+    /// and then the serializations of the children in order. The
+    /// serialization of a token is the byte `1`, the kind as a little-endian
+    /// `u16`, the length of the text in bytes as a little-endian `u32`, and
+    /// then the UTF-8 text. This format won't change in future versions.
+    pub fn write_content(&self, mut sink: impl FnMut(&[u8])) {
+        self.write_content_dyn(&mut sink)
+    }
+
+    fn write_content_dyn(&self, sink: &mut dyn FnMut(&[u8])) {
+        sink(&[0]);
+        sink(&self.kind().0.to_le_bytes());
+        sink(&(self.children().len() as u32).to_le_bytes());
+        for child in self.children() {
+            match child {
+                NodeOrToken::Node(it) => it.write_content_dyn(sink),
+                NodeOrToken::Token(it) => {
+                    sink(&[1]);
+                    sink(&it.kind().0.to_le_bytes());
+                    sink(&u32::from(it.text_len()).to_le_bytes());
+                    sink(it.text().as_bytes());
+                }
+            }
+        }
+    }
+
+    /// Returns all tokens of this tree in order, discarding the nodes. The
+    /// tree can be rebuilt as a flat node with [`from_token_stream`].
+    pub fn to_token_stream(&self) -> Vec<(SyntaxKind, GreenToken)> {
+        let mut res = Vec::new();
+        let mut stack = vec![self.slice().iter()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(GreenChild::Node { node, .. }) => stack.push(node.slice().iter()),
+                Some(GreenChild::Token { token, .. }) => res.push((token.kind(), token.clone())),
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns `true` if the text of this tree is exactly `src`, comparing
+    /// token by token without building the text of the tree. This is the
+    /// usual check that a parser is lossless.
+    pub fn text_matches(&self, src: &str) -> bool {
+        if TextSize::of(src) != self.text_len() {
+            return false;
+        }
+        let src = src.as_bytes();
+        self.text_chunks().all(|(offset, text)| {
+            let start = usize::from(offset);
+            &src[start..start + text.len()] == text.as_bytes()
+        })
+    }
+
+    /// Writes the text of this tree to `w` token by token, without building
+    /// the whole string in memory.
+    pub fn write_text<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.text_chunks().try_for_each(|(_, text)| w.write_all(text.as_bytes()))
+    }
+
+    /// Returns a copy of this tree which contains only the first `len` bytes
+    /// of the text. Nodes which cross the boundary are truncated recursively,
+    /// while tokens which cross it are dropped, so the text of the result can
+    /// be shorter than `len`.
+    #[must_use]
+    pub fn prefix(&self, len: TextSize) -> GreenNode {
+        let mut children = Vec::new();
+        for child in self.slice() {
+            let range = child.rel_range();
+            if range.end() <= len {
+                children.push(child.as_ref().to_owned());
+                continue;
+            }
+            if let NodeOrToken::Node(node) = child.as_ref() {
+                if range.start() < len {
+                    children.push(node.prefix(len - range.start()).into());
+                }
+            }
+            break;
+        }
+        GreenNode::new(self.kind(), children)
+    }
+
     pub(crate) fn child_at_range(
         &self,
         rel_range: TextRange,
@@ -148,12 +410,463 @@ impl GreenNodeData {
                 let child_range = it.rel_range();
                 TextRange::ordering(child_range, rel_range)
             })
-            // XXX: this handles empty ranges
+            // XXX: this handles empty ranges. Empty children never compare
+            // `Equal`, so they are only found here, by an empty `rel_range`
+            // at their offset, and only if they are the last child ending at
+            // that offset.
             .unwrap_or_else(|it| it.saturating_sub(1));
         let child = &self.slice().get(idx).filter(|it| it.rel_range().contains_range(rel_range))?;
         Some((idx, child.rel_offset(), child.as_ref()))
     }
 
+    /// Returns the deepest element which contains all of `ranges`, relative
+    /// to the start of this node. This is the covering element of the union
+    /// of the ranges, so it is found with a single descent. Returns this node
+    /// if `ranges` is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a range is out of bounds.
+    pub fn covering_element_multi(&self, ranges: &[TextRange]) -> GreenElementRef<'_> {
+        let mut res = NodeOrToken::Node(self);
+        let mut range = match ranges.iter().copied().reduce(TextRange::cover) {
+            Some(it) => it,
+            None => return res,
+        };
+        assert!(
+            TextRange::up_to(self.text_len()).contains_range(range),
+            "Bad range: node range {:?}, range {:?}",
+            TextRange::up_to(self.text_len()),
+            range,
+        );
+        while let NodeOrToken::Node(node) = res {
+            match node.child_at_range(range) {
+                Some((_, rel_offset, child)) => {
+                    res = child;
+                    range -= rel_offset;
+                }
+                None => break,
+            }
+        }
+        res
+    }
+
+    /// Counts the nodes and tokens of each kind in this tree, including this
+    /// node itself.
+    pub fn kind_histogram(&self) -> HashMap<SyntaxKind, usize> {
+        let mut res = HashMap::new();
+        *res.entry(self.kind()).or_insert(0) += 1;
+        let mut stack = vec![self.children()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    *res.entry(child.kind()).or_insert(0) += 1;
+                    if let NodeOrToken::Node(node) = child {
+                        stack.push(node.children());
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        res
+    }
+
+    /// Compares two trees, skipping the tokens for which `ignore` returns
+    /// `true`, such as whitespace or comments. Ignored tokens can be
+    /// interleaved differently in both trees.
+    pub fn eq_ignoring(&self, other: &GreenNodeData, ignore: impl Fn(SyntaxKind) -> bool) -> bool {
+        self.eq_ignoring_dyn(other, &ignore)
+    }
+
+    fn eq_ignoring_dyn(&self, other: &GreenNodeData, ignore: &dyn Fn(SyntaxKind) -> bool) -> bool {
+        if self.kind() != other.kind() {
+            return false;
+        }
+        let significant = |child: &GreenElementRef<'_>| !matches!(child, NodeOrToken::Token(it) if ignore(it.kind()));
+        let mut lhs = self.children().filter(significant);
+        let mut rhs = other.children().filter(significant);
+        loop {
+            match (lhs.next(), rhs.next()) {
+                (None, None) => return true,
+                (Some(NodeOrToken::Node(lhs)), Some(NodeOrToken::Node(rhs))) => {
+                    if !lhs.eq_ignoring_dyn(rhs, ignore) {
+                        return false;
+                    }
+                }
+                (Some(NodeOrToken::Token(lhs)), Some(NodeOrToken::Token(rhs))) => {
+                    if lhs != rhs {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Returns the ranges of all subtrees, including this node, which have
+    /// the same structure as `pattern`. Kinds are compared with
+    /// `match_kind(kind, pattern_kind)`, and tokens must also have the same
+    /// text.
+    pub fn find_subtrees<'a>(
+        &'a self,
+        pattern: &'a GreenNodeData,
+        match_kind: impl Fn(SyntaxKind, SyntaxKind) -> bool + 'a,
+    ) -> impl Iterator<Item = TextRange> + 'a {
+        let mut stack = vec![(TextSize::from(0), self.slice().iter())];
+        let nodes = iter::from_fn(move || loop {
+            let (offset, children) = stack.last_mut()?;
+            match children.next() {
+                Some(child) => {
+                    let offset = *offset + child.rel_offset();
+                    if let NodeOrToken::Node(node) = child.as_ref() {
+                        stack.push((offset, node.slice().iter()));
+                        return Some((offset, node));
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        });
+        iter::once((TextSize::from(0), self))
+            .chain(nodes)
+            .filter(move |(_, node)| node.matches_dyn(pattern, &match_kind))
+            .map(|(offset, node)| TextRange::at(offset, node.text_len()))
+    }
+
+    fn matches_dyn(
+        &self,
+        pattern: &GreenNodeData,
+        match_kind: &dyn Fn(SyntaxKind, SyntaxKind) -> bool,
+    ) -> bool {
+        // Cheap checks first, as most subtrees don't match.
+        if self.text_len() != pattern.text_len()
+            || self.children().len() != pattern.children().len()
+            || !match_kind(self.kind(), pattern.kind())
+        {
+            return false;
+        }
+        self.children().zip(pattern.children()).all(|pair| match pair {
+            (NodeOrToken::Node(node), NodeOrToken::Node(pattern)) => {
+                node.matches_dyn(pattern, match_kind)
+            }
+            (NodeOrToken::Token(token), NodeOrToken::Token(pattern)) => {
+                match_kind(token.kind(), pattern.kind()) && token.text() == pattern.text()
+            }
+            _ => false,
+        })
+    }
+
+    /// Returns a copy of this tree where the kind of every node and token is
+    /// replaced with `f(kind)`. Text and structure are unchanged.
+    #[must_use]
+    pub fn remap_kinds(&self, f: impl Fn(SyntaxKind) -> SyntaxKind) -> GreenNode {
+        self.remap_kinds_dyn(&f)
+    }
+
+    fn remap_kinds_dyn(&self, f: &dyn Fn(SyntaxKind) -> SyntaxKind) -> GreenNode {
+        let children = self.children().map(|child| match child {
+            NodeOrToken::Node(it) => it.remap_kinds_dyn(f).into(),
+            NodeOrToken::Token(it) => GreenToken::new(f(it.kind()), it.text()).into(),
+        });
+        GreenNode::new(f(self.kind()), children)
+    }
+
+    /// Returns a copy of this tree where every token for which `f` returns
+    /// `Some` is replaced with the returned token. Subtrees without replaced
+    /// tokens are shared with the original tree.
+    #[must_use]
+    pub fn map_tokens(&self, f: impl Fn(&GreenToken) -> Option<GreenToken>) -> GreenNode {
+        self.map_tokens_dyn(&f).unwrap_or_else(|| self.to_owned())
+    }
+
+    fn map_tokens_dyn(&self, f: &dyn Fn(&GreenToken) -> Option<GreenToken>) -> Option<GreenNode> {
+        let mut changed = false;
+        let children: Vec<GreenElement> = self
+            .slice()
+            .iter()
+            .map(|child| match child {
+                GreenChild::Node { node, .. } => match node.map_tokens_dyn(f) {
+                    Some(it) => {
+                        changed = true;
+                        it.into()
+                    }
+                    None => node.clone().into(),
+                },
+                GreenChild::Token { token, .. } => match f(token) {
+                    Some(it) => {
+                        changed = true;
+                        it.into()
+                    }
+                    None => token.clone().into(),
+                },
+            })
+            .collect();
+        if changed {
+            Some(GreenNode::new(self.kind(), children))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a canonical form of this tree, for order-insensitive
+    /// comparison.
+    ///
+    /// In every node, the children for which `sort_key` returns `Some` are
+    /// reordered by their keys, while the remaining children stay in place.
+    /// Keys are computed on the already canonicalized children. Note that this
+    /// changes the order of the text, so the result generally does not
+    /// correspond to the original source.
+    #[must_use]
+    pub fn canonicalize(
+        &self,
+        sort_key: impl Fn(GreenElementRef<'_>) -> Option<Vec<u8>>,
+    ) -> GreenNode {
+        self.canonicalize_dyn(&sort_key)
+    }
+
+    fn canonicalize_dyn(
+        &self,
+        sort_key: &dyn Fn(GreenElementRef<'_>) -> Option<Vec<u8>>,
+    ) -> GreenNode {
+        let mut children: Vec<GreenElement> = self
+            .children()
+            .map(|child| match child {
+                NodeOrToken::Node(it) => it.canonicalize_dyn(sort_key).into(),
+                NodeOrToken::Token(it) => it.to_owned().into(),
+            })
+            .collect();
+
+        let mut slots = Vec::new();
+        let mut sortable = Vec::new();
+        for (idx, child) in children.iter().enumerate() {
+            if let Some(key) = sort_key(child.as_deref()) {
+                slots.push(idx);
+                sortable.push((key, child.clone()));
+            }
+        }
+        sortable.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+        for (idx, (_, child)) in slots.into_iter().zip(sortable) {
+            children[idx] = child;
+        }
+
+        GreenNode::new(self.kind(), children)
+    }
+
+    /// Applies a text edit which lies within a single token, without
+    /// reparsing: the token is replaced with `reparse_token(new_text)` and
+    /// only the nodes above it are rebuilt.
+    ///
+    /// Returns `None` if `range` is not contained in a single token or if
+    /// `reparse_token` returns a token of a different kind, in which case the
+    /// edit needs a real reparse.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` doesn't fall on `char` boundaries.
+    #[must_use]
+    pub fn with_text_edit(
+        &self,
+        range: TextRange,
+        replacement: &str,
+        reparse_token: impl Fn(&str) -> GreenToken,
+    ) -> Option<GreenNode> {
+        self.with_text_edit_dyn(range, replacement, &reparse_token)
+    }
+
+    fn with_text_edit_dyn(
+        &self,
+        range: TextRange,
+        replacement: &str,
+        reparse_token: &dyn Fn(&str) -> GreenToken,
+    ) -> Option<GreenNode> {
+        let (index, rel_offset, child) = self.child_at_range(range)?;
+        let range = range - rel_offset;
+        let new_child: GreenElement = match child {
+            NodeOrToken::Node(node) => {
+                node.with_text_edit_dyn(range, replacement, reparse_token)?.into()
+            }
+            NodeOrToken::Token(token) => {
+                let mut text = token.text().to_string();
+                text.replace_range(ops::Range::<usize>::from(range), replacement);
+                let new_token = reparse_token(&text);
+                if new_token.kind() != token.kind() {
+                    return None;
+                }
+                new_token.into()
+            }
+        };
+        Some(self.replace_child(index, new_child))
+    }
+
+    /// Replaces several ranges of this tree at once, rebuilding only the
+    /// nodes which contain an edit and sharing all other subtrees.
+    ///
+    /// Each range must be exactly covered by a run of consecutive children
+    /// of some node, which are replaced with the given node. An empty range
+    /// inserts the node at that offset, between two children.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the edits are not sorted, if they overlap, or if a range
+    /// doesn't match the boundaries of children as described above.
+    #[must_use]
+    pub fn apply_edits(&self, edits: &[(TextRange, GreenNode)]) -> GreenNode {
+        for pair in edits.windows(2) {
+            assert!(
+                pair[0].0.end() <= pair[1].0.start(),
+                "edits overlap or are not sorted: {:?} {:?}",
+                pair[0].0,
+                pair[1].0
+            );
+        }
+        if let Some((range, _)) = edits.last() {
+            assert!(range.end() <= self.text_len(), "edit {:?} is out of bounds", range);
+        }
+        self.apply_edits_at(0.into(), edits)
+    }
+
+    fn apply_edits_at(&self, node_offset: TextSize, edits: &[(TextRange, GreenNode)]) -> GreenNode {
+        let mut children: Vec<GreenElement> = Vec::with_capacity(self.slice().len());
+        let mut edits = edits;
+        // End of the edit whose replacement covers the current children.
+        let mut replaced_until = None;
+        for child in self.slice() {
+            let range = TextRange::at(node_offset + child.rel_offset(), child.as_ref().text_len());
+            if let Some(end) = replaced_until {
+                if range.start() < end {
+                    assert!(range.end() <= end, "edit doesn't end at a child boundary: {:?}", end);
+                    continue;
+                }
+                replaced_until = None;
+            }
+            while let Some(((edit, replacement), rest)) = edits.split_first() {
+                if edit.start() != range.start() || !edit.is_empty() {
+                    break;
+                }
+                children.push(replacement.clone().into());
+                edits = rest;
+            }
+            match edits.split_first() {
+                Some(((edit, replacement), rest))
+                    if edit.start() == range.start() && edit.end() >= range.end() =>
+                {
+                    children.push(replacement.clone().into());
+                    edits = rest;
+                    if range.end() < edit.end() {
+                        replaced_until = Some(edit.end());
+                    }
+                }
+                Some((edit, _)) if edit.0.start() < range.end() => {
+                    let inner = edits.iter().take_while(|(it, _)| it.start() < range.end()).count();
+                    let (inner, rest) = edits.split_at(inner);
+                    let last = inner.last().unwrap().0;
+                    assert!(last.end() <= range.end(), "edit {:?} crosses a child boundary", last);
+                    match child {
+                        GreenChild::Node { node, .. } => {
+                            children.push(node.apply_edits_at(range.start(), inner).into())
+                        }
+                        GreenChild::Token { .. } => {
+                            panic!("edit {:?} is inside of a token", inner[0].0)
+                        }
+                    }
+                    edits = rest;
+                }
+                _ => children.push(child.as_ref().to_owned()),
+            }
+        }
+        assert!(replaced_until.is_none(), "edit doesn't end at a child boundary");
+        // Only insertions at the end of the node can remain.
+        children.extend(edits.iter().map(|(_, replacement)| replacement.clone().into()));
+        GreenNode::new(self.kind(), children)
+    }
+
+    /// Replaces the trivia between the token ending at `after.end()` and the
+    /// next significant token with `new_ws`, which is typically a single
+    /// whitespace token. This is the basic edit of a formatter.
+    ///
+    /// All tokens for which `is_trivia` returns `true` in the gap are
+    /// removed, even if they belong to different nodes. `new_ws` takes the
+    /// place of the first removed token, or is inserted before the next
+    /// token if there was no trivia. If `new_ws` is empty, the trivia is only
+    /// removed. Subtrees outside of the gap are shared with this tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `after.end()` is neither `0` nor the end of a token.
+    #[must_use]
+    pub fn set_gap(
+        &self,
+        after: TextRange,
+        new_ws: GreenToken,
+        is_trivia: impl Fn(SyntaxKind) -> bool,
+    ) -> GreenNode {
+        let offset = after.end();
+        let ws = if new_ws.text().is_empty() { None } else { Some(new_ws) };
+        let mut gap =
+            GapEdit { offset, is_trivia: &is_trivia, ws, anchor: offset == 0.into(), done: false };
+        let res = self.set_gap_dyn(0.into(), &mut gap);
+        assert!(gap.anchor, "no token ends at {:?}", offset);
+        let res = res.unwrap_or_else(|| self.to_owned());
+        match gap.ws {
+            Some(ws) => res.push_child(ws.into()),
+            None => res,
+        }
+    }
+
+    fn set_gap_dyn(&self, node_offset: TextSize, gap: &mut GapEdit<'_>) -> Option<GreenNode> {
+        let mut changed = false;
+        let mut children: Vec<GreenElement> = Vec::with_capacity(self.slice().len());
+        for child in self.slice() {
+            let range = TextRange::at(node_offset + child.rel_offset(), child.as_ref().text_len());
+            match child {
+                GreenChild::Node { node, .. } if !gap.done && range.end() >= gap.offset => {
+                    match node.set_gap_dyn(range.start(), gap) {
+                        Some(it) => {
+                            changed = true;
+                            children.push(it.into());
+                        }
+                        None => children.push(node.clone().into()),
+                    }
+                }
+                GreenChild::Token { token, .. } if !gap.done => {
+                    if range.start() < gap.offset {
+                        gap.anchor |= range.end() == gap.offset;
+                    } else {
+                        assert!(gap.anchor, "no token ends at {:?}", gap.offset);
+                        if (gap.is_trivia)(token.kind()) {
+                            changed = true;
+                            children.extend(gap.ws.take().map(GreenElement::from));
+                            continue;
+                        }
+                        gap.done = true;
+                        if let Some(ws) = gap.ws.take() {
+                            changed = true;
+                            children.push(ws.into());
+                        }
+                    }
+                    children.push(token.clone().into());
+                }
+                _ => children.push(child.as_ref().to_owned()),
+            }
+        }
+        if changed {
+            Some(GreenNode::new(self.kind(), children))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a copy of this node with a different kind. The children and
+    /// their offsets are copied as is, without recomputing anything.
+    #[must_use]
+    pub fn with_kind(&self, kind: SyntaxKind) -> GreenNode {
+        let head = GreenNodeHead { kind, text_len: self.text_len(), _c: Count::new() };
+        GreenNode { ptr: ThinArc::from_header_and_iter(head, self.slice().iter().cloned()) }
+    }
+
     #[must_use]
     pub fn replace_child(&self, index: usize, new_child: GreenElement) -> GreenNode {
         let mut replacement = Some(new_child);
@@ -166,6 +879,69 @@ impl GreenNodeData {
         });
         GreenNode::new(self.kind(), children)
     }
+
+    /// Returns a copy of this tree where the node `ptr` points to is replaced
+    /// with `replacement`, or `None` if `ptr` doesn't resolve in this tree.
+    ///
+    /// This node is treated as the root, and `ptr` is resolved exactly like
+    /// [`SyntaxNodePtr::to_node`] does.
+    #[must_use]
+    pub fn graft<L: Language>(
+        &self,
+        ptr: &SyntaxNodePtr<L>,
+        replacement: GreenNode,
+    ) -> Option<GreenNode> {
+        let kind = L::kind_to_raw(ptr.kind());
+        self.graft_at(0.into(), ptr.text_range(), kind, replacement)
+    }
+
+    fn graft_at(
+        &self,
+        offset: TextSize,
+        range: TextRange,
+        kind: SyntaxKind,
+        replacement: GreenNode,
+    ) -> Option<GreenNode> {
+        if TextRange::at(offset, self.text_len()) == range && self.kind() == kind {
+            return Some(replacement);
+        }
+        let (index, rel_offset, child) = self.child_at_range(range - offset)?;
+        let new_child =
+            child.into_node()?.graft_at(offset + rel_offset, range, kind, replacement)?;
+        Some(self.replace_child(index, new_child.into()))
+    }
+
+    /// Returns a new node with `new_child` appended to the children. This
+    /// copies all children, use [`GreenNodeParts`] to append many children.
+    #[must_use]
+    pub fn push_child(&self, new_child: GreenElement) -> GreenNode {
+        let len = self.children().len();
+        self.splice_children(len..len, iter::once(new_child))
+    }
+    /// Replaces several children at once, rebuilding the node only once. If
+    /// an index occurs several times, the last replacement wins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an index is out of bounds.
+    #[must_use]
+    pub fn replace_children<I>(&self, edits: I) -> GreenNode
+    where
+        I: IntoIterator<Item = (usize, GreenElement)>,
+    {
+        let mut children: Vec<_> = self.children().map(|it| it.to_owned()).collect();
+        let len = children.len();
+        for (index, new_child) in edits {
+            assert!(
+                index < len,
+                "child index {} is out of bounds, the node has {} children",
+                index,
+                len
+            );
+            children[index] = new_child;
+        }
+        GreenNode::new(self.kind(), children)
+    }
     #[must_use]
     pub fn insert_child(&self, index: usize, new_child: GreenElement) -> GreenNode {
         // https://github.com/rust-lang/rust/issues/34433
@@ -187,6 +963,17 @@ impl GreenNodeData {
     }
 }
 
+/// State of [`GreenNodeData::set_gap`] while walking the tree.
+struct GapEdit<'a> {
+    offset: TextSize,
+    is_trivia: &'a dyn Fn(SyntaxKind) -> bool,
+    ws: Option<GreenToken>,
+    /// Whether a token ends at `offset`.
+    anchor: bool,
+    /// Whether the next significant token after the gap was reached.
+    done: bool,
+}
+
 impl ops::Deref for GreenNode {
     type Target = GreenNodeData;
 
@@ -224,7 +1011,8 @@ impl GreenNode {
         );
 
         // XXX: fixup `text_len` after construction, because we can't iterate
-        // `children` twice.
+        // `children` twice. Callers which already have the children collected
+        // use `new_with_len` instead.
         let data = {
             let mut data = Arc::from_thin(data);
             Arc::get_mut(&mut data).unwrap().header.text_len = text_len;
@@ -234,6 +1022,37 @@ impl GreenNode {
         GreenNode { ptr: data }
     }
 
+    /// Creates new Node from an already collected slice of children. Unlike
+    /// `new`, this computes the text length before allocating the node, so
+    /// the node doesn't have to be fixed up afterwards.
+    pub fn from_boxed(kind: SyntaxKind, children: Box<[GreenElement]>) -> GreenNode {
+        let text_len = children.iter().map(|it| it.text_len()).sum();
+        GreenNode::new_with_len(kind, text_len, Vec::from(children).into_iter())
+    }
+
+    /// Like `new`, but with `text_len` known upfront, which must be the sum
+    /// of the lengths of `children`.
+    pub(crate) fn new_with_len<I>(kind: SyntaxKind, text_len: TextSize, children: I) -> GreenNode
+    where
+        I: ExactSizeIterator<Item = GreenElement>,
+    {
+        let mut rel_offset: TextSize = 0.into();
+        let children = children.map(|el| {
+            let child_offset = rel_offset;
+            rel_offset += el.text_len();
+            match el {
+                NodeOrToken::Node(node) => GreenChild::Node { rel_offset: child_offset, node },
+                NodeOrToken::Token(token) => GreenChild::Token { rel_offset: child_offset, token },
+            }
+        });
+        let data = ThinArc::from_header_and_iter(
+            GreenNodeHead { kind, text_len, _c: Count::new() },
+            children,
+        );
+        debug_assert_eq!(rel_offset, text_len);
+        GreenNode { ptr: data }
+    }
+
     #[inline]
     pub(crate) fn into_raw(this: GreenNode) -> ptr::NonNull<GreenNodeData> {
         let green = ManuallyDrop::new(this);
@@ -249,6 +1068,95 @@ impl GreenNode {
     }
 }
 
+/// Creates a node of the given `kind` whose children are the children of `a`
+/// followed by the children of `b`.
+///
+/// The kinds of `a` and `b` themselves are discarded.
+pub fn merge_nodes(kind: SyntaxKind, a: &GreenNode, b: &GreenNode) -> GreenNode {
+    let children: Vec<GreenElement> =
+        a.children().chain(b.children()).map(|it| it.to_owned()).collect();
+    GreenNode::new(kind, children)
+}
+
+/// Returns `new.text_len() - old.text_len()` as a signed value, which doesn't
+/// underflow when `new` is shorter than `old`.
+pub fn length_delta(old: &GreenNode, new: &GreenNode) -> i64 {
+    i64::from(u32::from(new.text_len())) - i64::from(u32::from(old.text_len()))
+}
+
+/// Creates a node of the given `kind` with `tokens` as its children, for
+/// example from the result of [`GreenNodeData::to_token_stream`]. Tokens are
+/// recreated if their kind differs from the kind they are paired with.
+pub fn from_token_stream(
+    kind: SyntaxKind,
+    tokens: impl IntoIterator<Item = (SyntaxKind, GreenToken)>,
+) -> GreenNode {
+    let children: Vec<GreenElement> = tokens
+        .into_iter()
+        .map(|(kind, token)| {
+            if token.kind() == kind {
+                token.into()
+            } else {
+                GreenToken::new(kind, token.text()).into()
+            }
+        })
+        .collect();
+    GreenNode::new(kind, children)
+}
+
+/// Accumulates the children of a node, to create the node once all of them
+/// are known. Unlike [`GreenNodeBuilder`](crate::GreenNodeBuilder), this
+/// doesn't support nesting.
+#[derive(Debug, Clone)]
+pub struct GreenNodeParts {
+    kind: SyntaxKind,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNodeParts {
+    pub fn new(kind: SyntaxKind) -> GreenNodeParts {
+        GreenNodeParts { kind, children: Vec::new() }
+    }
+
+    /// Adds new child after the already added ones.
+    pub fn push(&mut self, child: GreenElement) {
+        self.children.push(child)
+    }
+
+    /// Removes and returns the child at `index`, shifting the following
+    /// children to the left.
+    pub fn remove_child(&mut self, index: usize) -> GreenElement {
+        self.children.remove(index)
+    }
+
+    /// Returns a mutable reference to the child at `index`.
+    pub fn nth_mut(&mut self, index: usize) -> Option<&mut GreenElement> {
+        self.children.get_mut(index)
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: SyntaxKind) {
+        self.kind = kind
+    }
+
+    /// Returns the number of children added so far.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Creates the node out of the added children.
+    pub fn finish(self) -> GreenNode {
+        GreenNode::new(self.kind, self.children)
+    }
+}
+
 impl GreenChild {
     #[inline]
     pub(crate) fn as_ref(&self) -> GreenElementRef {
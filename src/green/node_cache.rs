@@ -1,6 +1,9 @@
 use hashbrown::hash_map::RawEntryMut;
 use rustc_hash::FxHasher;
-use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::{
+    hash::{BuildHasherDefault, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
 
 use crate::{
     green::GreenElementRef, GreenNode, GreenNodeData, GreenToken, GreenTokenData, NodeOrToken,
@@ -33,20 +36,126 @@ struct NoHash<T>(T);
 //
 // To fix that, we additionally wrap the data in `NoHash` wrapper, to make sure
 // we don't accidentally use the wrong hash!
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct NodeCache {
     nodes: HashMap<NoHash<GreenNode>, ()>,
+    tokens: Tokens,
+    max_children: usize,
+}
+
+#[derive(Debug)]
+enum Tokens {
+    Own(TokenInterner),
+    /// The shared interner, with a private front cache of the tokens this
+    /// cache already got from it, so that repeated tokens don't take the
+    /// lock.
+    Shared {
+        shared: Arc<Mutex<TokenInterner>>,
+        local: TokenInterner,
+    },
+}
+
+/// Interner for GreenTokens, which can be shared between several
+/// [`NodeCache`]s, see [`NodeCache::with_token_interner`].
+///
+/// Sharing one interner, for example between the caches of all files of a
+/// workspace, makes identical identifiers and keywords of different files
+/// share one allocation. To bound the memory of a long-lived interner, it
+/// can be given a capacity: once it holds that many tokens, new tokens are
+/// still created, but not interned.
+#[derive(Debug)]
+pub struct TokenInterner {
     tokens: HashMap<NoHash<GreenToken>, ()>,
+    capacity: usize,
+}
+
+impl Default for TokenInterner {
+    fn default() -> Self {
+        TokenInterner::with_capacity(usize::MAX)
+    }
+}
+
+impl TokenInterner {
+    /// Creates an interner which holds at most `capacity` tokens.
+    pub fn with_capacity(capacity: usize) -> TokenInterner {
+        TokenInterner { tokens: HashMap::default(), capacity }
+    }
+
+    /// Returns the number of interned tokens.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn get(&self, hash: u64, kind: SyntaxKind, text: &str) -> Option<GreenToken> {
+        self.tokens
+            .raw_entry()
+            .from_hash(hash, |token| token.0.kind() == kind && token.0.text() == text)
+            .map(|(token, ())| token.0.clone())
+    }
+
+    /// Returns the interned token equal to `token`, interning `token` itself
+    /// if there is none yet.
+    pub fn intern(&mut self, token: GreenToken) -> GreenToken {
+        let hash = token_hash(&token);
+        let is_full = self.tokens.len() >= self.capacity;
+        let entry = self
+            .tokens
+            .raw_entry_mut()
+            .from_hash(hash, |it| it.0.kind() == token.kind() && it.0.text() == token.text());
+        match entry {
+            RawEntryMut::Occupied(entry) => entry.key().0.clone(),
+            RawEntryMut::Vacant(_) if is_full => token,
+            RawEntryMut::Vacant(entry) => {
+                entry.insert_with_hasher(hash, NoHash(token.clone()), (), |t| token_hash(&t.0));
+                token
+            }
+        }
+    }
+
+    fn token(&mut self, kind: SyntaxKind, text: &str) -> (u64, GreenToken) {
+        let hash = text_hash(kind, text);
+        let is_full = self.tokens.len() >= self.capacity;
+        let entry = self
+            .tokens
+            .raw_entry_mut()
+            .from_hash(hash, |token| token.0.kind() == kind && token.0.text() == text);
+
+        let token = match entry {
+            RawEntryMut::Occupied(entry) => entry.key().0.clone(),
+            RawEntryMut::Vacant(_) if is_full => GreenToken::new(kind, text),
+            RawEntryMut::Vacant(entry) => {
+                let token = GreenToken::new(kind, text);
+                entry.insert_with_hasher(hash, NoHash(token.clone()), (), |t| token_hash(&t.0));
+                token
+            }
+        };
+
+        (hash, token)
+    }
+}
+
+impl Default for NodeCache {
+    fn default() -> Self {
+        NodeCache::with_max_children(3)
+    }
+}
+
+pub(crate) fn token_hash(token: &GreenTokenData) -> u64 {
+    text_hash(token.kind(), token.text())
 }
 
-fn token_hash(token: &GreenTokenData) -> u64 {
+fn text_hash(kind: SyntaxKind, text: &str) -> u64 {
     let mut h = FxHasher::default();
-    token.kind().hash(&mut h);
-    token.text().hash(&mut h);
+    kind.hash(&mut h);
+    text.hash(&mut h);
     h.finish()
 }
 
-fn node_hash(node: &GreenNodeData) -> u64 {
+pub(crate) fn node_hash(node: &GreenNodeData) -> u64 {
     let mut h = FxHasher::default();
     node.kind().hash(&mut h);
     for child in node.children() {
@@ -67,18 +176,138 @@ fn element_id(elem: GreenElementRef<'_>) -> *const () {
 }
 
 impl NodeCache {
+    /// Creates a cache which interns nodes with at most `max_children`
+    /// children, and whose children are all interned in turn.
+    ///
+    /// The default of 3 covers the small nodes which repeat most often.
+    /// Raising the limit lets whole subtrees, like identical function
+    /// bodies in generated code, be shared, at the cost of hashing and
+    /// storing more nodes. `usize::MAX` interns every node.
+    pub fn with_max_children(max_children: usize) -> NodeCache {
+        NodeCache {
+            nodes: HashMap::default(),
+            tokens: Tokens::Own(TokenInterner::default()),
+            max_children,
+        }
+    }
+
+    /// Creates a cache which interns its tokens in `interner` instead of in
+    /// a private interner, so that they are shared with all other caches
+    /// using the same interner.
+    ///
+    /// The cache remembers the tokens it got from `interner`, so the lock is
+    /// only taken the first time this cache sees a token. A poisoned lock is
+    /// ignored, as the interner stays consistent even if a thread panicked
+    /// while holding it.
+    pub fn with_token_interner(interner: Arc<Mutex<TokenInterner>>) -> NodeCache {
+        let tokens = Tokens::Shared { shared: interner, local: TokenInterner::default() };
+        NodeCache { tokens, ..NodeCache::default() }
+    }
+
+    /// Looks the token up in the front cache of a shared interner, and falls
+    /// back to `f` on the shared interner itself.
+    fn with_tokens(
+        &mut self,
+        kind: SyntaxKind,
+        text: &str,
+        f: impl FnOnce(&mut TokenInterner) -> GreenToken,
+    ) -> GreenToken {
+        match &mut self.tokens {
+            Tokens::Own(it) => f(it),
+            Tokens::Shared { shared, local } => {
+                let hash = text_hash(kind, text);
+                if let Some(token) = local.get(hash, kind, text) {
+                    return token;
+                }
+                let token = f(&mut shared.lock().unwrap_or_else(|err| err.into_inner()));
+                local.intern(token.clone());
+                token
+            }
+        }
+    }
+
+    /// Interns a token ahead of time, for example for keywords.
+    ///
+    /// Builders using this cache will reuse the returned token for every
+    /// token with the same kind and text, so such tokens can be compared by
+    /// pointer.
+    pub fn preintern(&mut self, kind: SyntaxKind, text: &str) -> GreenToken {
+        self.token(kind, text).1
+    }
+
+    /// Returns the cached token equal to `token`, caching `token` itself if
+    /// there is none yet.
+    pub fn intern_token(&mut self, token: GreenToken) -> GreenToken {
+        let key = token.clone();
+        self.with_tokens(key.kind(), key.text(), |it| it.intern(token))
+    }
+
+    /// Interns all tokens and subtrees of `node`, so that trees built with
+    /// this cache afterwards share them, for example when incrementally
+    /// reparsing a tree loaded from disk.
+    ///
+    /// Only nodes which this cache would intern when building them are
+    /// stored, see [`NodeCache::with_max_children`].
+    pub fn warm_from(&mut self, node: &GreenNode) {
+        self.intern_subtree(node);
+    }
+
+    /// Interns `node` and its descendants bottom-up, returning the cached
+    /// node and its hash, or `0` if the node can't be cached.
+    fn intern_subtree(&mut self, node: &GreenNodeData) -> (u64, GreenNode) {
+        let mut children: Vec<(u64, GreenElement)> = node
+            .children()
+            .map(|child| match child {
+                NodeOrToken::Node(it) => {
+                    let (hash, node) = self.intern_subtree(it);
+                    (hash, node.into())
+                }
+                NodeOrToken::Token(it) => {
+                    let token = self.intern_token(it.to_owned());
+                    (token_hash(&token), token.into())
+                }
+            })
+            .collect();
+        // Keep `node` itself if all of its children are already the cached
+        // ones, so that the cache shares with the original tree.
+        let unchanged = node
+            .children()
+            .map(element_id)
+            .eq(children.iter().map(|(_, it)| element_id(it.as_deref())));
+        self.node_or_existing(node.kind(), &mut children, 0, unchanged.then_some(node))
+    }
+
     pub(crate) fn node(
         &mut self,
         kind: SyntaxKind,
         children: &mut Vec<(u64, GreenElement)>,
         first_child: usize,
+    ) -> (u64, GreenNode) {
+        self.node_or_existing(kind, children, first_child, None)
+    }
+
+    /// Like `node`, but if `existing` is given, it is used instead of
+    /// building a new node from `children`, which must be its children.
+    fn node_or_existing(
+        &mut self,
+        kind: SyntaxKind,
+        children: &mut Vec<(u64, GreenElement)>,
+        first_child: usize,
+        existing: Option<&GreenNodeData>,
     ) -> (u64, GreenNode) {
         let build_node = move |children: &mut Vec<(u64, GreenElement)>| {
-            GreenNode::new(kind, children.drain(first_child..).map(|(_, it)| it))
+            if let Some(existing) = existing {
+                children.truncate(first_child);
+                return existing.to_owned();
+            }
+            // The children are already collected, so the length can be
+            // computed before creating the node.
+            let text_len = children[first_child..].iter().map(|(_, it)| it.text_len()).sum();
+            GreenNode::new_with_len(kind, text_len, children.drain(first_child..).map(|(_, it)| it))
         };
 
         let children_ref = &children[first_child..];
-        if children_ref.len() > 3 {
+        if children_ref.len() > self.max_children {
             let node = build_node(children);
             return (0, node);
         }
@@ -131,27 +360,7 @@ impl NodeCache {
     }
 
     pub(crate) fn token(&mut self, kind: SyntaxKind, text: &str) -> (u64, GreenToken) {
-        let hash = {
-            let mut h = FxHasher::default();
-            kind.hash(&mut h);
-            text.hash(&mut h);
-            h.finish()
-        };
-
-        let entry = self
-            .tokens
-            .raw_entry_mut()
-            .from_hash(hash, |token| token.0.kind() == kind && token.0.text() == text);
-
-        let token = match entry {
-            RawEntryMut::Occupied(entry) => entry.key().0.clone(),
-            RawEntryMut::Vacant(entry) => {
-                let token = GreenToken::new(kind, text);
-                entry.insert_with_hasher(hash, NoHash(token.clone()), (), |t| token_hash(&t.0));
-                token
-            }
-        };
-
-        (hash, token)
+        let token = self.with_tokens(kind, text, |it| it.token(kind, text).1);
+        (text_hash(kind, text), token)
     }
 }
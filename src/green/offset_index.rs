@@ -0,0 +1,74 @@
+//! A flat index of the tokens of a green tree, for repeated offset lookups.
+
+use crate::{
+    green::{GreenChild, GreenNodeData, GreenToken},
+    TextRange, TextSize,
+};
+
+/// Maps offsets to the tokens of a tree, see
+/// [`GreenNodeData::build_offset_index`].
+///
+/// Lookups are a binary search, instead of a descent from the root.
+#[derive(Debug, Clone)]
+pub struct OffsetIndex {
+    /// Non-empty tokens with their start offsets, in order.
+    tokens: Vec<(TextSize, GreenToken)>,
+}
+
+impl OffsetIndex {
+    /// Returns the token which contains `offset` together with its range.
+    /// If `offset` is at the boundary of two tokens, the token to the right
+    /// is returned. Returns `None` if `offset` is at or past the end of the
+    /// text.
+    ///
+    /// Empty tokens don't contain any offset, so they are never returned.
+    pub fn token_at(&self, offset: TextSize) -> Option<(TextRange, GreenToken)> {
+        let idx = self.tokens.partition_point(|(start, _)| *start <= offset).checked_sub(1)?;
+        let (start, token) = &self.tokens[idx];
+        let range = TextRange::at(*start, token.text_len());
+        if range.contains(offset) {
+            Some((range, token.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of indexed tokens.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns `true` if the tree has no non-empty tokens.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
+
+impl GreenNodeData {
+    /// Collects the tokens of this tree into an [`OffsetIndex`], which
+    /// answers "token at offset" queries faster than descending the tree,
+    /// at the cost of memory.
+    pub fn build_offset_index(&self) -> OffsetIndex {
+        let mut tokens = Vec::new();
+        let mut stack = vec![(TextSize::from(0), self.slice().iter())];
+        while let Some((offset, children)) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    let offset = *offset + child.rel_offset();
+                    match child {
+                        GreenChild::Node { node, .. } => stack.push((offset, node.slice().iter())),
+                        GreenChild::Token { token, .. } => {
+                            if !token.text().is_empty() {
+                                tokens.push((offset, token.clone()))
+                            }
+                        }
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        OffsetIndex { tokens }
+    }
+}
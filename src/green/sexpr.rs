@@ -0,0 +1,232 @@
+//! A compact single-line text format for green trees, for golden tests.
+//!
+//! Nodes are written as `(kind child child ...)` and tokens as `kind:"text"`,
+//! where `kind` is the raw value of the `SyntaxKind` and the text uses Rust
+//! string escapes.
+//!
+//! There is also a multi-line snapshot format without ranges, see
+//! [`GreenNodeData::to_snapshot`].
+
+use std::{error::Error, fmt, fmt::Write};
+
+use crate::{
+    green::{GreenElement, GreenNode, GreenNodeData, GreenToken, SyntaxKind},
+    NodeOrToken,
+};
+
+/// An error produced by [`GreenNode::from_sexpr`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    offset: usize,
+    message: String,
+}
+
+impl ParseError {
+    /// Byte offset of the error in the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Error for ParseError {}
+
+impl GreenNodeData {
+    /// Serializes the tree into a single line of text, which can be parsed
+    /// back with [`GreenNode::from_sexpr`].
+    pub fn to_sexpr(&self) -> String {
+        let mut buf = String::new();
+        write_sexpr(self, &mut buf);
+        buf
+    }
+
+    /// Renders the tree with one node or token per line, indented by depth:
+    /// nodes as `KIND` and tokens as `KIND "text"`, with kinds named by
+    /// `kind_name`. Unlike the `Debug` output of `SyntaxNode`, there are no
+    /// offsets, so an edit only changes the lines of the edited elements,
+    /// which makes the result suitable for golden files.
+    pub fn to_snapshot(&self, kind_name: impl Fn(SyntaxKind) -> &'static str) -> String {
+        let mut buf = String::new();
+        writeln!(buf, "{}", kind_name(self.kind())).unwrap();
+        let mut stack = vec![self.children()];
+        while let Some(children) = stack.last_mut() {
+            let child = match children.next() {
+                Some(it) => it,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let indent = stack.len() * 2;
+            match child {
+                NodeOrToken::Node(it) => {
+                    writeln!(buf, "{:indent$}{}", "", kind_name(it.kind()), indent = indent)
+                        .unwrap();
+                    stack.push(it.children());
+                }
+                NodeOrToken::Token(it) => writeln!(
+                    buf,
+                    "{:indent$}{} {:?}",
+                    "",
+                    kind_name(it.kind()),
+                    it.text(),
+                    indent = indent
+                )
+                .unwrap(),
+            }
+        }
+        buf
+    }
+}
+
+fn write_sexpr(node: &GreenNodeData, buf: &mut String) {
+    write!(buf, "({}", node.kind().0).unwrap();
+    for child in node.children() {
+        buf.push(' ');
+        match child {
+            NodeOrToken::Node(it) => write_sexpr(it, buf),
+            NodeOrToken::Token(it) => write!(buf, "{}:{:?}", it.kind().0, it.text()).unwrap(),
+        }
+    }
+    buf.push(')');
+}
+
+impl GreenNode {
+    /// Parses a tree serialized with [`GreenNodeData::to_sexpr`].
+    ///
+    /// `kind_lookup` converts the kinds as they appear in the text into
+    /// `SyntaxKind`s. This allows golden files to use readable kind names
+    /// instead of the raw values emitted by `to_sexpr`.
+    pub fn from_sexpr(
+        text: &str,
+        kind_lookup: impl Fn(&str) -> SyntaxKind,
+    ) -> Result<GreenNode, ParseError> {
+        let mut parser = Parser { text, pos: 0, kind_lookup: &kind_lookup };
+        parser.skip_ws();
+        let node = parser.node()?;
+        parser.skip_ws();
+        if parser.pos != text.len() {
+            return Err(parser.error("expected end of input"));
+        }
+        Ok(node)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    kind_lookup: &'a dyn Fn(&str) -> SyntaxKind,
+}
+
+impl Parser<'_> {
+    fn node(&mut self) -> Result<GreenNode, ParseError> {
+        self.expect('(')?;
+        let kind = self.kind()?;
+        let mut children: Vec<GreenElement> = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                Some(')') => {
+                    self.bump();
+                    break;
+                }
+                Some('(') => children.push(self.node()?.into()),
+                Some(_) => {
+                    let kind = self.kind()?;
+                    self.expect(':')?;
+                    let text = self.string()?;
+                    children.push(GreenToken::new(kind, &text).into());
+                }
+                None => return Err(self.error("expected `)`")),
+            }
+        }
+        Ok(GreenNode::new(kind, children))
+    }
+
+    fn kind(&mut self) -> Result<SyntaxKind, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "():\"".contains(c) {
+                break;
+            }
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(self.error("expected kind"));
+        }
+        Ok((self.kind_lookup)(&self.text[start..self.pos]))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut res = String::new();
+        loop {
+            let c = self.bump().ok_or_else(|| self.error("unterminated string"))?;
+            match c {
+                '"' => return Ok(res),
+                '\\' => res.push(self.escape()?),
+                c => res.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let c = match self.bump() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('u') => {
+                self.expect('{')?;
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                    self.bump();
+                }
+                let hex = &self.text[start..self.pos];
+                self.expect('}')?;
+                u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("invalid unicode escape"))?
+            }
+            _ => return Err(self.error("invalid escape")),
+        };
+        Ok(c)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected `{}`", c)));
+        }
+        self.bump();
+        Ok(())
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { offset: self.pos, message: message.to_string() }
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    borrow::Borrow,
+    borrow::{Borrow, Cow},
     fmt,
     mem::{self, ManuallyDrop},
     ops, ptr,
@@ -104,6 +104,12 @@ impl GreenTokenData {
     pub fn text_len(&self) -> TextSize {
         TextSize::of(self.text())
     }
+
+    /// Returns a new token of the same kind with the given text.
+    #[must_use]
+    pub fn with_text(&self, text: &str) -> GreenToken {
+        GreenToken::new(self.kind(), text)
+    }
 }
 
 impl GreenToken {
@@ -114,6 +120,16 @@ impl GreenToken {
         let ptr = ThinArc::from_header_and_iter(head, text.bytes());
         GreenToken { ptr }
     }
+
+    /// Creates a token from either borrowed or owned text, for lexers which
+    /// mostly slice the source but sometimes synthesize text. The text is
+    /// copied into the token's allocation in both cases, so an owned string
+    /// is dropped afterwards; to share equal tokens, pass the result to
+    /// [`NodeCache::intern_token`](crate::NodeCache::intern_token).
+    #[inline]
+    pub fn new_cow(kind: SyntaxKind, text: Cow<'_, str>) -> GreenToken {
+        GreenToken::new(kind, &text)
+    }
     #[inline]
     pub(crate) fn into_raw(this: GreenToken) -> ptr::NonNull<GreenTokenData> {
         let green = ManuallyDrop::new(this);
@@ -16,6 +16,7 @@ pub mod cursor;
 pub mod api;
 mod syntax_text;
 mod utility_types;
+mod line_index;
 
 mod cow_mut;
 #[allow(unsafe_code)]
@@ -30,12 +31,20 @@ pub use text_size::{TextLen, TextRange, TextSize};
 
 pub use crate::{
     api::{
-        Language, SyntaxElement, SyntaxElementChildren, SyntaxNode, SyntaxNodeChildren, SyntaxToken,
+        KindTable, Language, SyntaxElement, SyntaxElementChildren, SyntaxNode, SyntaxNodeChildren,
+        SyntaxToken, Zipper,
     },
+    cursor::NodeId,
     green::{
-        Checkpoint, Children, GreenNode, GreenNodeBuilder, GreenNodeData, GreenToken,
-        GreenTokenData, SyntaxKind,
+        build_from_events, from_token_stream, length_delta, merge_nodes, tree_edit_distance,
+        Checkpoint, Children, Event, GreenBuilderOptions, GreenNode, GreenNodeBuilder,
+        GreenNodeData, GreenNodeParts, GreenToken, GreenTokenData, HashTree, NodeCache,
+        OffsetIndex, ParseError, SpanMap, SyntaxKind, TokenInterner,
     },
-    syntax_text::SyntaxText,
+    line_index::{LineCol, LineIndex},
+    syntax_text::{SyntaxText, SyntaxTextBuf},
     utility_types::{Direction, NodeOrToken, TokenAtOffset, WalkEvent},
 };
+
+#[cfg(feature = "serde1")]
+pub use crate::serde_impls::SyntaxNodeJson;
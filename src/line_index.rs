@@ -0,0 +1,173 @@
+//! Conversion between byte offsets and line/column positions.
+//!
+//! Columns are measured in UTF-16 code units, which is what the Language
+//! Server Protocol uses.
+
+use rustc_hash::FxHashMap;
+
+use crate::{TextLen, TextSize};
+
+/// Zero-based line and UTF-16 column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// Maps byte offsets in a text to [`LineCol`] positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    /// Offset of the beginning of each line.
+    newlines: Vec<TextSize>,
+    /// Non-ASCII characters on each line, with offsets relative to the start
+    /// of the line.
+    wide_chars: FxHashMap<u32, Vec<WideChar>>,
+    /// Length of the whole text.
+    len: TextSize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WideChar {
+    start: TextSize,
+    end: TextSize,
+}
+
+impl WideChar {
+    fn len(&self) -> TextSize {
+        self.end - self.start
+    }
+
+    fn len_utf16(&self) -> u32 {
+        if self.len() == TextSize::from(4) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> LineIndex {
+        let mut newlines = vec![0.into()];
+        let mut wide_chars = FxHashMap::default();
+        let mut line_wide_chars = Vec::new();
+
+        let mut line_start: TextSize = 0.into();
+        let mut offset: TextSize = 0.into();
+        for c in text.chars() {
+            let c_len = c.text_len();
+            if c == '\n' {
+                if !line_wide_chars.is_empty() {
+                    let line = newlines.len() as u32 - 1;
+                    wide_chars.insert(line, std::mem::take(&mut line_wide_chars));
+                }
+                line_start = offset + c_len;
+                newlines.push(line_start);
+            } else if !c.is_ascii() {
+                let start = offset - line_start;
+                line_wide_chars.push(WideChar { start, end: start + c_len });
+            }
+            offset += c_len;
+        }
+        if !line_wide_chars.is_empty() {
+            let line = newlines.len() as u32 - 1;
+            wide_chars.insert(line, line_wide_chars);
+        }
+
+        LineIndex { newlines, wide_chars, len: offset }
+    }
+
+    /// Returns the line and UTF-16 column of `offset`, which must be at a
+    /// `char` boundary.
+    pub fn line_col(&self, offset: TextSize) -> LineCol {
+        let line = self.newlines.partition_point(|&it| it <= offset) - 1;
+        let col = offset - self.newlines[line];
+        let line = line as u32;
+        LineCol { line, col: self.utf8_to_utf16_col(line, col) }
+    }
+
+    /// Returns the offset of the position at UTF-16 column `utf16_col` of
+    /// `line`, the inverse of [`line_col`](Self::line_col).
+    ///
+    /// Returns `None` if the line doesn't exist, if the column is past the
+    /// end of the line, or if it points into the middle of a character, as
+    /// between the two halves of a surrogate pair. Clients which want to
+    /// clamp such positions instead have to do so themselves.
+    pub fn offset_utf16(&self, line: u32, utf16_col: u32) -> Option<TextSize> {
+        let line_start = *self.newlines.get(line as usize)?;
+        // The newline itself isn't part of the line.
+        let line_end = match self.newlines.get(line as usize + 1) {
+            Some(&next) => next - TextSize::from(1),
+            None => self.len,
+        };
+
+        let mut col = utf16_col;
+        if let Some(wide_chars) = self.wide_chars.get(&line) {
+            for c in wide_chars {
+                let start = u32::from(c.start) - (col - utf16_col);
+                if utf16_col <= start {
+                    break;
+                }
+                if utf16_col < start + c.len_utf16() {
+                    return None;
+                }
+                col += u32::from(c.len()) - c.len_utf16();
+            }
+        }
+
+        let offset = line_start.checked_add(col.into())?;
+        if offset > line_end {
+            return None;
+        }
+        Some(offset)
+    }
+
+    fn utf8_to_utf16_col(&self, line: u32, col: TextSize) -> u32 {
+        let mut res: u32 = col.into();
+        if let Some(wide_chars) = self.wide_chars.get(&line) {
+            for c in wide_chars {
+                if c.end > col {
+                    break;
+                }
+                res -= u32::from(c.len()) - c.len_utf16();
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let index = LineIndex::new("ab\ncé\n😀x\n");
+        let check = |offset: u32, line, col| {
+            assert_eq!(index.line_col(offset.into()), LineCol { line, col }, "{}", offset)
+        };
+        check(0, 0, 0);
+        check(2, 0, 2);
+        check(3, 1, 0);
+        check(4, 1, 1);
+        check(6, 1, 2);
+        check(7, 2, 0);
+        check(11, 2, 2);
+        check(12, 2, 3);
+        check(13, 3, 0);
+    }
+
+    #[test]
+    fn test_offset_utf16() {
+        let text = "ab\ncé\n😀x\n";
+        let index = LineIndex::new(text);
+        for offset in text.char_indices().map(|(it, _)| it).chain(Some(text.len())) {
+            let offset = TextSize::from(offset as u32);
+            let LineCol { line, col } = index.line_col(offset);
+            assert_eq!(index.offset_utf16(line, col), Some(offset), "{:?}", offset);
+        }
+        assert_eq!(index.offset_utf16(2, 1), None);
+        assert_eq!(index.offset_utf16(0, 3), None);
+        assert_eq!(index.offset_utf16(4, 0), None);
+    }
+}
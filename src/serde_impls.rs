@@ -3,7 +3,7 @@ use std::fmt;
 
 use crate::{
     api::{Language, SyntaxNode, SyntaxToken},
-    NodeOrToken,
+    LineCol, LineIndex, NodeOrToken, TextRange,
 };
 
 struct SerDisplay<T>(T);
@@ -64,3 +64,305 @@ impl<L: Language> Serialize for Children<&'_ SyntaxNode<L>> {
         state.end()
     }
 }
+
+/// Serializes a tree with line/column positions instead of byte offsets, for
+/// frontends which can't convert offsets themselves.
+///
+/// Nodes are emitted as `{kind, start, end, children}` and tokens as
+/// `{kind, start, end, text}`, where `start` and `end` are `{line, col}`
+/// positions computed with the [`LineIndex`] of the whole file.
+pub struct SyntaxNodeJson<'a, L: Language>(pub &'a SyntaxNode<L>, pub &'a LineIndex);
+
+impl<L: Language> Serialize for SyntaxNodeJson<'_, L> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let SyntaxNodeJson(node, line_index) = *self;
+        let mut state = serializer.serialize_map(Some(4))?;
+        state.serialize_entry("kind", &SerDisplay(DisplayDebug(node.kind())))?;
+        serialize_positions(&mut state, line_index, node.text_range())?;
+        state.serialize_entry("children", &JsonChildren(node, line_index))?;
+        state.end()
+    }
+}
+
+struct SyntaxTokenJson<'a, L: Language>(&'a SyntaxToken<L>, &'a LineIndex);
+
+impl<L: Language> Serialize for SyntaxTokenJson<'_, L> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let SyntaxTokenJson(token, line_index) = *self;
+        let mut state = serializer.serialize_map(Some(4))?;
+        state.serialize_entry("kind", &SerDisplay(DisplayDebug(token.kind())))?;
+        serialize_positions(&mut state, line_index, token.text_range())?;
+        state.serialize_entry("text", token.text())?;
+        state.end()
+    }
+}
+
+fn serialize_positions<M: SerializeMap>(
+    state: &mut M,
+    line_index: &LineIndex,
+    range: TextRange,
+) -> Result<(), M::Error> {
+    state.serialize_entry("start", &SerLineCol(line_index.line_col(range.start())))?;
+    state.serialize_entry("end", &SerLineCol(line_index.line_col(range.end())))
+}
+
+struct SerLineCol(LineCol);
+
+impl Serialize for SerLineCol {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_map(Some(2))?;
+        state.serialize_entry("line", &self.0.line)?;
+        state.serialize_entry("col", &self.0.col)?;
+        state.end()
+    }
+}
+
+struct JsonChildren<'a, L: Language>(&'a SyntaxNode<L>, &'a LineIndex);
+
+impl<L: Language> Serialize for JsonChildren<'_, L> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_seq(None)?;
+        self.0.children_with_tokens().try_for_each(|element| match element {
+            NodeOrToken::Node(it) => state.serialize_element(&SyntaxNodeJson(&it, self.1)),
+            NodeOrToken::Token(it) => state.serialize_element(&SyntaxTokenJson(&it, self.1)),
+        })?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{self, Write};
+
+    use serde::ser::{self, Impossible};
+
+    use super::*;
+    use crate::{GreenNode, SyntaxKind};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum TestLanguage {}
+
+    impl Language for TestLanguage {
+        type Kind = SyntaxKind;
+
+        fn kind_from_raw(raw: SyntaxKind) -> SyntaxKind {
+            raw
+        }
+        fn kind_to_raw(kind: SyntaxKind) -> SyntaxKind {
+            kind
+        }
+    }
+
+    /// Serializes to compact JSON, supporting only what trees emit.
+    struct Json(String);
+
+    #[derive(Debug)]
+    struct Unsupported;
+
+    impl fmt::Display for Unsupported {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("unsupported")
+        }
+    }
+
+    impl ser::StdError for Unsupported {}
+
+    impl ser::Error for Unsupported {
+        fn custom<T: fmt::Display>(_: T) -> Unsupported {
+            Unsupported
+        }
+    }
+
+    macro_rules! unsupported {
+        ($($name:ident($($arg:ty),*) -> $ret:ty;)*) => {
+            $(fn $name(self, $(_: $arg),*) -> Result<$ret, Unsupported> {
+                Err(Unsupported)
+            })*
+        };
+    }
+
+    impl<'a> ser::Serializer for &'a mut Json {
+        type Ok = ();
+        type Error = Unsupported;
+        type SerializeSeq = Compound<'a>;
+        type SerializeTuple = Impossible<(), Unsupported>;
+        type SerializeTupleStruct = Impossible<(), Unsupported>;
+        type SerializeTupleVariant = Impossible<(), Unsupported>;
+        type SerializeMap = Compound<'a>;
+        type SerializeStruct = Impossible<(), Unsupported>;
+        type SerializeStructVariant = Impossible<(), Unsupported>;
+
+        fn serialize_u32(self, v: u32) -> Result<(), Unsupported> {
+            write!(self.0, "{}", v).map_err(ser::Error::custom)
+        }
+        fn serialize_str(self, v: &str) -> Result<(), Unsupported> {
+            write!(self.0, "{:?}", v).map_err(ser::Error::custom)
+        }
+        fn collect_str<T: ?Sized + fmt::Display>(self, v: &T) -> Result<(), Unsupported> {
+            self.serialize_str(&v.to_string())
+        }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Compound<'a>, Unsupported> {
+            self.0.push('[');
+            Ok(Compound { json: self, first: true, close: ']' })
+        }
+        fn serialize_map(self, _: Option<usize>) -> Result<Compound<'a>, Unsupported> {
+            self.0.push('{');
+            Ok(Compound { json: self, first: true, close: '}' })
+        }
+        fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<(), Unsupported> {
+            Err(Unsupported)
+        }
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: &T,
+        ) -> Result<(), Unsupported> {
+            Err(Unsupported)
+        }
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<(), Unsupported> {
+            Err(Unsupported)
+        }
+
+        unsupported! {
+            serialize_bool(bool) -> ();
+            serialize_i8(i8) -> ();
+            serialize_i16(i16) -> ();
+            serialize_i32(i32) -> ();
+            serialize_i64(i64) -> ();
+            serialize_u8(u8) -> ();
+            serialize_u16(u16) -> ();
+            serialize_u64(u64) -> ();
+            serialize_f32(f32) -> ();
+            serialize_f64(f64) -> ();
+            serialize_char(char) -> ();
+            serialize_bytes(&[u8]) -> ();
+            serialize_none() -> ();
+            serialize_unit() -> ();
+            serialize_unit_struct(&'static str) -> ();
+            serialize_unit_variant(&'static str, u32, &'static str) -> ();
+            serialize_tuple(usize) -> Impossible<(), Unsupported>;
+            serialize_tuple_struct(&'static str, usize) -> Impossible<(), Unsupported>;
+            serialize_tuple_variant(&'static str, u32, &'static str, usize)
+                -> Impossible<(), Unsupported>;
+            serialize_struct(&'static str, usize) -> Impossible<(), Unsupported>;
+            serialize_struct_variant(&'static str, u32, &'static str, usize)
+                -> Impossible<(), Unsupported>;
+        }
+    }
+
+    struct Compound<'a> {
+        json: &'a mut Json,
+        first: bool,
+        close: char,
+    }
+
+    impl Compound<'_> {
+        fn separate(&mut self) {
+            if !std::mem::take(&mut self.first) {
+                self.json.0.push(',');
+            }
+        }
+    }
+
+    impl ser::SerializeSeq for Compound<'_> {
+        type Ok = ();
+        type Error = Unsupported;
+
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, v: &T) -> Result<(), Unsupported> {
+            self.separate();
+            v.serialize(&mut *self.json)
+        }
+        fn end(self) -> Result<(), Unsupported> {
+            self.json.0.push(self.close);
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeMap for Compound<'_> {
+        type Ok = ();
+        type Error = Unsupported;
+
+        fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Unsupported> {
+            self.separate();
+            key.serialize(&mut *self.json)?;
+            self.json.0.push(':');
+            Ok(())
+        }
+        fn serialize_value<T: ?Sized + Serialize>(&mut self, v: &T) -> Result<(), Unsupported> {
+            v.serialize(&mut *self.json)
+        }
+        fn end(self) -> Result<(), Unsupported> {
+            self.json.0.push(self.close);
+            Ok(())
+        }
+    }
+
+    fn to_json(value: impl Serialize) -> String {
+        let mut json = Json(String::new());
+        value.serialize(&mut json).unwrap();
+        json.0
+    }
+
+    #[test]
+    fn test_syntax_node_json() {
+        let green = GreenNode::from_sexpr(r#"(0 1:"a" 2:"\n" (3 1:"😀b") (4))"#, |kind| {
+            SyntaxKind(kind.parse().unwrap())
+        })
+        .unwrap();
+        let root = SyntaxNode::<TestLanguage>::new_root(green);
+        let line_index = LineIndex::new(&root.text().to_string());
+
+        let pos = |line, col| format!(r#"{{"line":{},"col":{}}}"#, line, col);
+        let token = |kind, start: String, end: String, text| {
+            format!(
+                r#"{{"kind":"SyntaxKind({})","start":{},"end":{},"text":{}}}"#,
+                kind, start, end, text
+            )
+        };
+        let node = |kind, start: String, end: String, children: &[String]| {
+            format!(
+                r#"{{"kind":"SyntaxKind({})","start":{},"end":{},"children":[{}]}}"#,
+                kind,
+                start,
+                end,
+                children.join(",")
+            )
+        };
+        let inner = node(3, pos(1, 0), pos(1, 3), &[token(1, pos(1, 0), pos(1, 3), r#""😀b""#)]);
+        let empty = node(4, pos(1, 3), pos(1, 3), &[]);
+        let expected = node(
+            0,
+            pos(0, 0),
+            pos(1, 3),
+            &[
+                token(1, pos(0, 0), pos(0, 1), r#""a""#),
+                token(2, pos(0, 1), pos(1, 0), r#""\n""#),
+                inner.clone(),
+                empty.clone(),
+            ],
+        );
+        assert_eq!(to_json(SyntaxNodeJson(&root, &line_index)), expected);
+
+        let children: Vec<_> = root.children().collect();
+        assert_eq!(to_json(SyntaxNodeJson(&children[0], &line_index)), inner);
+        assert_eq!(to_json(SyntaxNodeJson(&children[1], &line_index)), empty);
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str};
 
 use crate::{
     cursor::{SyntaxNode, SyntaxToken},
@@ -57,6 +57,87 @@ impl SyntaxText {
         found(res)
     }
 
+    /// Returns the number of `char`s in the text.
+    pub fn char_len(&self) -> usize {
+        let mut res = 0;
+        self.for_each_chunk(|chunk| res += chunk.chars().count());
+        res
+    }
+
+    /// Returns the length of the text in UTF-16 code units.
+    pub fn len_utf16(&self) -> usize {
+        let mut res = 0;
+        self.for_each_chunk(|chunk| res += chunk.chars().map(char::len_utf16).sum::<usize>());
+        res
+    }
+
+    /// Converts a byte offset into the text to the number of `char`s before
+    /// it. Returns `None` if the offset is out of bounds or doesn't fall on a
+    /// `char` boundary.
+    pub fn byte_to_char(&self, offset: TextSize) -> Option<usize> {
+        if offset > self.len() {
+            return None;
+        }
+        let mut start: TextSize = 0.into();
+        let mut chars = 0;
+        let res = self.try_for_each_chunk(|chunk| {
+            let end = start + TextSize::of(chunk);
+            if offset < end {
+                let off: usize = u32::from(offset - start) as usize;
+                return Err(chunk.get(..off).map(|prefix| chars + prefix.chars().count()));
+            }
+            chars += chunk.chars().count();
+            start = end;
+            Ok(())
+        });
+        match res {
+            Ok(()) => Some(chars),
+            Err(it) => it,
+        }
+    }
+
+    /// Returns `true` if the text starts with `prefix`.
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        TextSize::of(prefix) <= self.len() && self.bytes_eq_at(0.into(), prefix)
+    }
+
+    /// Returns `true` if the text ends with `suffix`.
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        match self.len().checked_sub(TextSize::of(suffix)) {
+            Some(start) => self.bytes_eq_at(start, suffix),
+            None => false,
+        }
+    }
+
+    /// Compares the bytes of the text starting at `offset` with `text`,
+    /// without requiring `offset` to be at a `char` boundary.
+    fn bytes_eq_at(&self, offset: TextSize, text: &str) -> bool {
+        let mut skip = usize::from(offset);
+        let mut rest = text.as_bytes();
+        let res = self.try_for_each_chunk(|chunk| {
+            let chunk = chunk.as_bytes();
+            if skip >= chunk.len() {
+                skip -= chunk.len();
+                return Ok(());
+            }
+            let chunk = &chunk[skip..];
+            skip = 0;
+            let len = chunk.len().min(rest.len());
+            if chunk[..len] != rest[..len] {
+                return Err(false);
+            }
+            rest = &rest[len..];
+            if rest.is_empty() {
+                return Err(true);
+            }
+            Ok(())
+        });
+        match res {
+            Ok(()) => rest.is_empty(),
+            Err(it) => it,
+        }
+    }
+
     pub fn slice<R: private::SyntaxTextRange>(&self, range: R) -> SyntaxText {
         let start = range.start().unwrap_or_default();
         let end = range.end().unwrap_or(self.len());
@@ -103,6 +184,18 @@ impl SyntaxText {
         }
     }
 
+    /// Copies the text into a [`SyntaxTextBuf`], which doesn't keep the tree
+    /// alive.
+    pub fn to_buf(&self) -> SyntaxTextBuf {
+        let mut text = String::with_capacity(usize::from(self.len()));
+        let mut chunk_ends = Vec::new();
+        self.for_each_chunk(|chunk| {
+            text.push_str(chunk);
+            chunk_ends.push(TextSize::of(text.as_str()));
+        });
+        SyntaxTextBuf { text, chunk_ends, range: self.range }
+    }
+
     fn tokens_with_ranges(&self) -> impl Iterator<Item = (SyntaxToken, TextRange)> {
         let text_range = self.range;
         self.node.descendants_with_tokens().filter_map(|element| element.into_token()).filter_map(
@@ -208,6 +301,92 @@ fn zip_texts<I: Iterator<Item = (SyntaxToken, TextRange)>>(xs: &mut I, ys: &mut
 
 impl Eq for SyntaxText {}
 
+/// An owned copy of a [`SyntaxText`], which remembers the range of the text
+/// in the tree and the boundaries of the tokens it was made of.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxTextBuf {
+    text: String,
+    /// End offsets of the chunks, relative to the start of the text.
+    chunk_ends: Vec<TextSize>,
+    range: TextRange,
+}
+
+impl SyntaxTextBuf {
+    pub fn len(&self) -> TextSize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// Returns the range of the original text in the tree.
+    pub fn text_range(&self) -> TextRange {
+        self.range
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the parts of the text which came from individual tokens.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        let mut start = 0;
+        self.chunk_ends.iter().map(move |&end| {
+            let end = usize::from(end);
+            let chunk = &self.text[start..end];
+            start = end;
+            chunk
+        })
+    }
+
+    pub fn contains_char(&self, c: char) -> bool {
+        self.text.contains(c)
+    }
+
+    pub fn find_char(&self, c: char) -> Option<TextSize> {
+        self.text.find(c).map(|pos| TextSize::from(pos as u32))
+    }
+
+    /// Returns the offset of the first occurrence of `needle`, relative to
+    /// the start of the text.
+    pub fn find(&self, needle: &str) -> Option<TextSize> {
+        self.text.find(needle).map(|pos| TextSize::from(pos as u32))
+    }
+
+    pub fn char_at(&self, offset: TextSize) -> Option<char> {
+        self.text.get(usize::from(offset)..)?.chars().next()
+    }
+
+    pub fn lines(&self) -> str::Lines<'_> {
+        self.text.lines()
+    }
+}
+
+impl fmt::Display for SyntaxTextBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.text, f)
+    }
+}
+
+impl From<SyntaxTextBuf> for String {
+    fn from(text: SyntaxTextBuf) -> String {
+        text.text
+    }
+}
+
+impl PartialEq<str> for SyntaxTextBuf {
+    fn eq(&self, rhs: &str) -> bool {
+        self.text == rhs
+    }
+}
+
+impl PartialEq<&'_ str> for SyntaxTextBuf {
+    fn eq(&self, rhs: &&str) -> bool {
+        self.text == *rhs
+    }
+}
+
 mod private {
     use std::ops;
 
@@ -266,12 +445,14 @@ mod private {
 
 #[cfg(test)]
 mod tests {
-    use crate::{green::SyntaxKind, GreenNodeBuilder};
+    use crate::{green::SyntaxKind, GreenBuilderOptions, GreenNodeBuilder};
 
     use super::*;
 
     fn build_tree(chunks: &[&str]) -> SyntaxNode {
-        let mut builder = GreenNodeBuilder::new();
+        // Some of the tests use empty chunks.
+        let options = GreenBuilderOptions { forbid_empty_tokens: false, ..Default::default() };
+        let mut builder = GreenNodeBuilder::new().with_options(options);
         builder.start_node(SyntaxKind(62));
         for &chunk in chunks.iter() {
             builder.token(SyntaxKind(92), chunk.into())
@@ -308,4 +489,45 @@ mod tests {
         check(&["{", "abc", "}"], &["{", "123", "}", "{"]);
         check(&["{", "abc", "}ab"], &["{", "abc", "}", "ab"]);
     }
+
+    #[test]
+    fn test_byte_to_char() {
+        let text = build_tree(&["a", "\u{e9}b", "", "\u{1F600}"]).text();
+        assert_eq!(text.char_len(), 4);
+        assert_eq!(text.byte_to_char(0.into()), Some(0));
+        assert_eq!(text.byte_to_char(1.into()), Some(1));
+        assert_eq!(text.byte_to_char(2.into()), None);
+        assert_eq!(text.byte_to_char(3.into()), Some(2));
+        assert_eq!(text.byte_to_char(4.into()), Some(3));
+        assert_eq!(text.byte_to_char(6.into()), None);
+        assert_eq!(text.byte_to_char(8.into()), Some(4));
+        assert_eq!(text.byte_to_char(9.into()), None);
+    }
+
+    #[test]
+    fn test_starts_ends_with() {
+        let text = build_tree(&["//", "! ab", "", "\u{e9}"]).text();
+        assert!(text.starts_with(""));
+        assert!(text.starts_with("//!"));
+        assert!(text.starts_with("//! ab\u{e9}"));
+        assert!(!text.starts_with("/!"));
+        assert!(!text.starts_with("//! ab\u{e9}c"));
+        assert!(text.ends_with(""));
+        assert!(text.ends_with("b\u{e9}"));
+        assert!(text.ends_with("//! ab\u{e9}"));
+        assert!(!text.ends_with("a\u{e9}"));
+        assert!(!text.ends_with("\u{a9}"));
+    }
+
+    #[test]
+    fn test_to_buf() {
+        let text = build_tree(&["fn", " ", "", "f\n", "x"]).text();
+        let buf = text.slice(1.into()..).to_buf();
+        assert_eq!(buf, "n f\nx");
+        assert_eq!(buf.text_range(), TextRange::new(1.into(), 6.into()));
+        assert_eq!(buf.chunks().collect::<Vec<_>>(), vec!["n", " ", "", "f\n", "x"]);
+        assert_eq!(buf.find("x"), Some(4.into()));
+        assert_eq!(buf.char_at(2.into()), Some('f'));
+        assert_eq!(buf.lines().collect::<Vec<_>>(), vec!["n f", "x"]);
+    }
 }
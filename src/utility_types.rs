@@ -40,6 +40,24 @@ impl<N, T> NodeOrToken<N, T> {
     }
 }
 
+impl<T> NodeOrToken<T, T> {
+    /// Returns the node or the token, if both have the same type.
+    ///
+    /// ```
+    /// use rowan::NodeOrToken;
+    ///
+    /// let node: NodeOrToken<&str, &str> = NodeOrToken::Node("node");
+    /// let token: NodeOrToken<&str, &str> = NodeOrToken::Token("token");
+    /// assert_eq!(node.flatten(), "node");
+    /// assert_eq!(token.flatten(), "token");
+    /// ```
+    pub fn flatten(self) -> T {
+        match self {
+            NodeOrToken::Node(it) | NodeOrToken::Token(it) => it,
+        }
+    }
+}
+
 impl<N: Deref, T: Deref> NodeOrToken<N, T> {
     pub(crate) fn as_deref(&self) -> NodeOrToken<&N::Target, &T::Target> {
         match self {